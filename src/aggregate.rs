@@ -0,0 +1,760 @@
+use crate::crawl::{
+    CrawlFailure, CrawlOutcome, CrawlResult, CrawlWarning, DiscoveryReport, MbinCrawlResult,
+    OptedOutInstance, OtherSoftwareInstance, PrivateApiInstance, WarningKind,
+};
+use crate::directory::InstanceDirectoryEntry;
+use crate::graph::{FederationGraph, GraphAnalysis};
+use crate::migrate::SCHEMA_VERSION;
+use crate::recommend::recommended_domains;
+use crate::structs::GetSiteResponse;
+use crate::CrawlOutput;
+use chrono::{DateTime, Utc};
+use lemmy_api_common_v019::lemmy_db_schema::RegistrationMode;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// TODO: lemmy stores these numbers in SiteAggregates, would be good to simply use that as a member
+//       (to avoid many members). but SiteAggregates also has id, site_id fields
+#[derive(Debug, Clone, Serialize)]
+pub struct TotalStats {
+    pub schema_version: u64,
+    /// ULID of the crawl, to find it in the history store, logs and metrics
+    pub run_id: String,
+    pub crawled_instances: i32,
+    pub total_users: i64,
+    pub users_active_day: i64,
+    pub users_active_week: i64,
+    pub users_active_month: i64,
+    pub users_active_halfyear: i64,
+    pub instance_details: Vec<CrawlResult>,
+    pub failed_instances: Vec<CrawlFailure>,
+    /// Instances running other fediverse software, only with `--count-other-software`
+    pub other_software: Vec<OtherSoftwareInstance>,
+    /// Mbin and Kbin instances, only with `--include-mbin`. Not included in the totals above.
+    pub mbin_instances: Vec<MbinCrawlResult>,
+    /// Only with `--crawl-communities`
+    pub community_stats: Option<TotalCommunityStats>,
+    /// Instances which require login to access the API. Included in the totals above with user
+    /// counts from nodeinfo, which has no daily or weekly active users.
+    pub private_api_instances: Vec<PrivateApiInstance>,
+    /// Instances which opted out of detailed data collection. Included in the totals above with
+    /// user counts from nodeinfo.
+    pub opted_out_instances: Vec<OptedOutInstance>,
+    /// Number of distinct linked instances per software, as reported by crawled instances
+    pub linked_software: BTreeMap<String, usize>,
+    /// Crawled instances and their users per Lemmy API version
+    pub version_distribution: BTreeMap<String, VersionCount>,
+    /// Crawled instances and their active users per allowed discussion language code
+    pub language_distribution: BTreeMap<String, LanguageCount>,
+    /// How open crawled instances are to new users
+    pub registration: RegistrationStats,
+    /// Crawled instances which fail to send activities to other crawled instances, most failures
+    /// first
+    pub federation_lag: Vec<FederationLag>,
+    /// Domains which are blocked or allowlisted by crawled instances, most blocked first
+    pub defederation: Vec<DefederationCount>,
+    /// Connected components of the federation between crawled instances, and instances which
+    /// are cut off from the main component
+    pub graph_analysis: GraphAnalysis,
+    /// Lemmy instances grouped by monthly active users, smallest first. Instances with private
+    /// API or which opted out are not included, as their post counts are unknown.
+    pub size_buckets: Vec<SizeBucket>,
+    /// Non-fatal issues found during crawl and aggregation
+    pub warnings: Vec<CrawlWarning>,
+    /// Linked domains which were not crawled, by reason
+    pub discovery: DiscoveryReport,
+    /// The crawl was interrupted, so totals only include instances crawled until then
+    pub partial: bool,
+}
+
+impl TotalStats {
+    /// Set small per-instance counts to 0, so that individual users of tiny instances can't be
+    /// identified in published data. Totals are not affected.
+    pub fn suppress_small_counts(&mut self, threshold: i64) {
+        for i in &mut self.instance_details {
+            i.suppress_small_counts(threshold);
+        }
+        for i in &mut self.other_software {
+            i.suppress_small_counts(threshold);
+        }
+        for i in &mut self.mbin_instances {
+            i.suppress_small_counts(threshold);
+        }
+        for i in &mut self.private_api_instances {
+            i.node_info.suppress_small_counts(threshold);
+        }
+        for i in &mut self.opted_out_instances {
+            i.suppress_small_counts(threshold);
+        }
+    }
+
+    pub fn totals(&self) -> Totals {
+        Totals {
+            run_id: self.run_id.clone(),
+            crawled_instances: self.crawled_instances,
+            failed_instances: self.failed_instances.len(),
+            total_users: self.total_users,
+            users_active_day: self.users_active_day,
+            users_active_week: self.users_active_week,
+            users_active_month: self.users_active_month,
+            users_active_halfyear: self.users_active_halfyear,
+            partial: self.partial,
+        }
+    }
+}
+
+/// Community totals across all crawled instances. Details of each community are included in the
+/// instance details.
+#[derive(Debug, Clone, Serialize)]
+pub struct TotalCommunityStats {
+    pub total_communities: usize,
+    pub nsfw_communities: usize,
+    /// Instances where communities couldn't be listed
+    pub failed_instances: usize,
+}
+
+/// Network wide totals, without details of individual instances
+#[derive(Debug, Serialize)]
+pub struct Totals {
+    pub run_id: String,
+    pub crawled_instances: i32,
+    pub failed_instances: usize,
+    pub total_users: i64,
+    pub users_active_day: i64,
+    pub users_active_week: i64,
+    pub users_active_month: i64,
+    pub users_active_halfyear: i64,
+    pub partial: bool,
+}
+
+/// Lower bounds of monthly active users of the instance size buckets, after the first bucket
+/// which starts at 0
+const SIZE_BUCKET_BOUNDS: [i64; 4] = [10, 100, 1_000, 10_000];
+
+/// Totals of instances within a range of monthly active users
+#[derive(Debug, Clone, Serialize)]
+pub struct SizeBucket {
+    pub min_users_active_month: i64,
+    /// Exclusive, missing for the largest bucket
+    pub max_users_active_month: Option<i64>,
+    pub instances: usize,
+    pub total_users: i64,
+    pub users_active_month: i64,
+    pub posts: i64,
+    /// Fractions of the sums over all buckets, between 0 and 1
+    pub share_of_users: f64,
+    pub share_of_active_users: f64,
+    pub share_of_posts: f64,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+struct BucketCounts {
+    instances: usize,
+    total_users: i64,
+    users_active_month: i64,
+    posts: i64,
+}
+
+/// Totals which are updated as each result arrives, so that no extra pass over all results is
+/// needed after the crawl. Also counts results which are spilled to disk without communities.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct RunningTotals {
+    crawled_instances: i32,
+    total_users: i64,
+    users_active_day: i64,
+    users_active_week: i64,
+    users_active_month: i64,
+    users_active_halfyear: i64,
+    /// Instances where communities were listed
+    community_instances: usize,
+    total_communities: usize,
+    nsfw_communities: usize,
+    community_failed_instances: usize,
+    /// Software of each linked instance, as reported by crawled instances
+    linked_software: HashMap<String, Option<String>>,
+    #[serde(default)]
+    size_buckets: [BucketCounts; SIZE_BUCKET_BOUNDS.len() + 1],
+    warnings: Vec<CrawlWarning>,
+}
+
+impl RunningTotals {
+    pub(crate) fn crawled_instances(&self) -> i32 {
+        self.crawled_instances
+    }
+
+    pub(crate) fn add(&mut self, outcome: &CrawlOutcome) {
+        match outcome {
+            CrawlOutcome::Success(i) => self.add_result(i),
+            CrawlOutcome::PrivateApi(i) => {
+                let users = &i.node_info.usage.users;
+                self.add_nodeinfo_users(users.total, users.active_month, users.active_halfyear);
+            }
+            CrawlOutcome::OptedOut(i) => self.add_nodeinfo_users(
+                i.total_users,
+                i.users_active_month,
+                i.users_active_halfyear,
+            ),
+            _ => {}
+        }
+    }
+
+    fn add_result(&mut self, i: &CrawlResult) {
+        self.crawled_instances += 1;
+        self.total_users += i.site_info.total_users();
+        self.users_active_day += i.site_info.users_active_day();
+        self.users_active_week += i.site_info.users_active_week();
+        self.users_active_month += i.site_info.users_active_month();
+        self.users_active_halfyear += i.site_info.users_active_half_year();
+
+        let users_active_month = i.site_info.users_active_month();
+        let bucket = SIZE_BUCKET_BOUNDS
+            .iter()
+            .filter(|b| users_active_month >= **b)
+            .count();
+        let bucket = &mut self.size_buckets[bucket];
+        bucket.instances += 1;
+        bucket.total_users += i.site_info.total_users();
+        bucket.users_active_month += users_active_month;
+        bucket.posts += i.site_info.posts();
+
+        match &i.communities {
+            Some(communities) => {
+                self.community_instances += 1;
+                self.total_communities += communities.len();
+                self.nsfw_communities += communities.iter().filter(|c| c.community.nsfw).count();
+            }
+            None if i.communities_requested => self.community_failed_instances += 1,
+            None => {}
+        }
+
+        let linked = i.federated_instances.as_ref().map(|f| f.linked_software());
+        for (domain, software) in linked.into_iter().flatten() {
+            let entry = self.linked_software.entry(domain).or_insert(None);
+            // Some instances haven't fetched the software yet, prefer those which have
+            if entry.is_none() {
+                *entry = software;
+            }
+        }
+
+        // Totals use the site info
+        if i.node_info.usage.users.total != i.site_info.total_users() {
+            self.warnings.push(CrawlWarning {
+                domain: i.domain.clone(),
+                kind: WarningKind::CountMismatch,
+                message: format!(
+                    "nodeinfo reports {} users, site info {}",
+                    i.node_info.usage.users.total,
+                    i.site_info.total_users()
+                ),
+            });
+        }
+    }
+
+    /// Nodeinfo has no daily or weekly active users
+    fn add_nodeinfo_users(&mut self, total: i64, active_month: i64, active_halfyear: i64) {
+        self.crawled_instances += 1;
+        self.total_users += total;
+        self.users_active_month += active_month;
+        self.users_active_halfyear += active_halfyear;
+    }
+
+    /// Returns `None` if communities weren't requested from any instance
+    fn community_stats(&self) -> Option<TotalCommunityStats> {
+        (self.community_instances + self.community_failed_instances > 0).then_some(
+            TotalCommunityStats {
+                total_communities: self.total_communities,
+                nsfw_communities: self.nsfw_communities,
+                failed_instances: self.community_failed_instances,
+            },
+        )
+    }
+
+    fn size_buckets(&self) -> Vec<SizeBucket> {
+        let sum =
+            |count: fn(&BucketCounts) -> i64| self.size_buckets.iter().map(count).sum::<i64>();
+        let total_users = sum(|b| b.total_users);
+        let users_active_month = sum(|b| b.users_active_month);
+        let posts = sum(|b| b.posts);
+        let share = |part: i64, total: i64| {
+            if total > 0 {
+                part as f64 / total as f64
+            } else {
+                0.0
+            }
+        };
+        self.size_buckets
+            .iter()
+            .enumerate()
+            .map(|(i, b)| SizeBucket {
+                min_users_active_month: i.checked_sub(1).map_or(0, |i| SIZE_BUCKET_BOUNDS[i]),
+                max_users_active_month: SIZE_BUCKET_BOUNDS.get(i).copied(),
+                instances: b.instances,
+                total_users: b.total_users,
+                users_active_month: b.users_active_month,
+                posts: b.posts,
+                share_of_users: share(b.total_users, total_users),
+                share_of_active_users: share(b.users_active_month, users_active_month),
+                share_of_posts: share(b.posts, posts),
+            })
+            .collect()
+    }
+
+    /// Count which software the network federates with, using the software names which crawled
+    /// instances report for their linked instances. Those instances don't need to be crawled.
+    fn linked_software_census(&self) -> BTreeMap<String, usize> {
+        let mut census = BTreeMap::new();
+        for software in self.linked_software.values() {
+            let software = software.as_deref().unwrap_or("unknown");
+            *census.entry(software.to_lowercase()).or_default() += 1;
+        }
+        census
+    }
+}
+
+/// Fail count from which outgoing federation is reported as lagging
+const LAGGING_FAIL_COUNT: i32 = 3;
+/// Fail count from which outgoing federation is reported as dead. Lemmy waits 2^fail_count
+/// seconds between retries, so this takes about a day of failures.
+const DEAD_FAIL_COUNT: i32 = 16;
+
+/// Outgoing federation from one crawled instance to another which keeps failing
+#[derive(Debug, Clone, Serialize)]
+pub struct FederationLag {
+    pub from: String,
+    pub to: String,
+    pub fail_count: i32,
+    pub last_successful_published_time: Option<DateTime<Utc>>,
+    pub dead: bool,
+}
+
+fn federation_lag(results: &[CrawlResult]) -> Vec<FederationLag> {
+    let crawled: HashSet<&str> = results.iter().map(|r| r.domain.as_str()).collect();
+    let mut lag: Vec<_> = results
+        .iter()
+        .flat_map(|r| {
+            r.federation_states
+                .iter()
+                .filter(|s| s.fail_count >= LAGGING_FAIL_COUNT)
+                .filter(|s| crawled.contains(s.domain.as_str()))
+                .map(move |s| FederationLag {
+                    from: r.domain.clone(),
+                    to: s.domain.clone(),
+                    fail_count: s.fail_count,
+                    last_successful_published_time: s.last_successful_published_time,
+                    dead: s.fail_count >= DEAD_FAIL_COUNT,
+                })
+        })
+        .collect();
+    lag.sort_by(|a, b| {
+        b.fail_count
+            .cmp(&a.fail_count)
+            .then_with(|| (&a.from, &a.to).cmp(&(&b.from, &b.to)))
+    });
+    lag
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct VersionCount {
+    pub instances: usize,
+    pub total_users: i64,
+    pub users_active_month: i64,
+}
+
+fn version_distribution(results: &[CrawlResult]) -> BTreeMap<String, VersionCount> {
+    let mut versions: BTreeMap<String, VersionCount> = BTreeMap::new();
+    for r in results {
+        let count = versions.entry(r.site_info.version()).or_default();
+        count.instances += 1;
+        count.total_users += r.site_info.total_users();
+        count.users_active_month += r.site_info.users_active_month();
+    }
+    versions
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct LanguageCount {
+    /// Instances which allow the language for discussions
+    pub instances: usize,
+    /// Monthly active users of those instances
+    pub users_active_month: i64,
+    /// Monthly active users of each instance split evenly across its languages, so that
+    /// instances allowing all languages don't dominate every language
+    pub weighted_users_active_month: f64,
+}
+
+fn language_distribution(results: &[CrawlResult]) -> BTreeMap<String, LanguageCount> {
+    let mut languages: BTreeMap<String, LanguageCount> = BTreeMap::new();
+    for r in results {
+        let discussion_languages = r.site_info.discussion_languages();
+        let users_active_month = r.site_info.users_active_month();
+        let weight = users_active_month as f64 / discussion_languages.len().max(1) as f64;
+        for language in discussion_languages {
+            let count = languages.entry(language).or_default();
+            count.instances += 1;
+            count.users_active_month += users_active_month;
+            count.weighted_users_active_month += weight;
+        }
+    }
+    languages
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RegistrationStats {
+    /// By registration mode, eg `Open` or `RequireApplication`
+    pub modes: BTreeMap<String, InstanceShare>,
+    pub captcha_enabled: InstanceShare,
+}
+
+/// Crawled instances with some property, and how many of all users they have
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct InstanceShare {
+    pub instances: usize,
+    pub total_users: i64,
+    /// Fraction of the users of all crawled instances, between 0 and 1
+    pub user_share: f64,
+}
+
+fn registration_stats(results: &[CrawlResult]) -> RegistrationStats {
+    let mut stats = RegistrationStats::default();
+    let mut all_users = 0;
+    for r in results {
+        let total_users = r.site_info.total_users();
+        all_users += total_users;
+        let mode = format!("{:?}", r.site_info.registration_mode());
+        let mut shares = vec![stats.modes.entry(mode).or_default()];
+        if r.site_info.captcha_enabled() {
+            shares.push(&mut stats.captcha_enabled);
+        }
+        for share in shares {
+            share.instances += 1;
+            share.total_users += total_users;
+        }
+    }
+    if all_users > 0 {
+        for share in stats.modes.values_mut().chain([&mut stats.captcha_enabled]) {
+            share.user_share = share.total_users as f64 / all_users as f64;
+        }
+    }
+    stats
+}
+
+/// How many crawled instances block or allowlist a domain
+#[derive(Debug, Clone, Serialize)]
+pub struct DefederationCount {
+    pub domain: String,
+    pub blocked_by: usize,
+    pub allowed_by: usize,
+}
+
+fn defederation(results: &[CrawlResult]) -> Vec<DefederationCount> {
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new();
+    for federated in results
+        .iter()
+        .filter_map(|r| r.federated_instances.as_ref())
+    {
+        for domain in federated.blocked() {
+            counts.entry(domain).or_default().0 += 1;
+        }
+        for domain in federated.allowed() {
+            counts.entry(domain).or_default().1 += 1;
+        }
+    }
+    let mut defederation: Vec<_> = counts
+        .into_iter()
+        .map(|(domain, (blocked_by, allowed_by))| DefederationCount {
+            domain,
+            blocked_by,
+            allowed_by,
+        })
+        .collect();
+    defederation.sort_by(|a, b| {
+        b.blocked_by
+            .cmp(&a.blocked_by)
+            .then_with(|| a.domain.cmp(&b.domain))
+    });
+    defederation
+}
+
+pub fn aggregate(crawl_output: CrawlOutput) -> TotalStats {
+    let running = crawl_output.totals;
+    let mut warnings = crawl_output.warnings;
+    warnings.extend(running.warnings.iter().cloned());
+    warnings.sort_by(|a, b| a.domain.cmp(&b.domain));
+    let federation_lag = federation_lag(&crawl_output.results);
+    let defederation = defederation(&crawl_output.results);
+    let graph_analysis = FederationGraph::new(&crawl_output.results).analyze();
+    let version_distribution = version_distribution(&crawl_output.results);
+    let language_distribution = language_distribution(&crawl_output.results);
+    let registration = registration_stats(&crawl_output.results);
+    TotalStats {
+        schema_version: SCHEMA_VERSION,
+        run_id: crawl_output.run_id,
+        crawled_instances: running.crawled_instances,
+        total_users: running.total_users,
+        users_active_day: running.users_active_day,
+        users_active_week: running.users_active_week,
+        users_active_halfyear: running.users_active_halfyear,
+        users_active_month: running.users_active_month,
+        instance_details: crawl_output.results,
+        failed_instances: crawl_output.failures,
+        other_software: crawl_output.other_software,
+        mbin_instances: crawl_output.mbin_results,
+        community_stats: running.community_stats(),
+        private_api_instances: crawl_output.private_api,
+        opted_out_instances: crawl_output.opted_out,
+        linked_software: running.linked_software_census(),
+        version_distribution,
+        language_distribution,
+        registration,
+        federation_lag,
+        defederation,
+        graph_analysis,
+        size_buckets: running.size_buckets(),
+        warnings,
+        discovery: crawl_output.discovery,
+        partial: crawl_output.partial,
+    }
+}
+
+/// Instance details as needed by join-lemmy.org
+#[derive(Debug, Serialize)]
+pub struct JoinLemmyInstance {
+    #[serde(flatten)]
+    pub directory: InstanceDirectoryEntry,
+    pub version: String,
+    pub total_users: i64,
+    pub users_active_month: i64,
+    pub posts: i64,
+    pub comments: i64,
+    /// Shown to new users, see `recommended_domains`
+    pub recommended: bool,
+}
+
+/// Instance list for join-lemmy.org, sorted by monthly active users
+pub fn joinlemmy_instance_data(stats: &TotalStats) -> Vec<JoinLemmyInstance> {
+    let recommended = recommended_domains(&stats.instance_details);
+    stats
+        .instance_details
+        .iter()
+        .map(|i| JoinLemmyInstance {
+            directory: InstanceDirectoryEntry::new(i),
+            version: i.site_info.version(),
+            total_users: i.site_info.total_users(),
+            users_active_month: i.site_info.users_active_month(),
+            posts: i.site_info.posts(),
+            comments: i.site_info.comments(),
+            recommended: recommended.contains(&i.domain),
+        })
+        .collect()
+}
+
+/// Totals with only the most important counts of each instance
+#[derive(Debug, Serialize)]
+pub struct MinimalStats {
+    #[serde(flatten)]
+    pub totals: Totals,
+    pub instances: Vec<MinimalInstance>,
+    pub warnings: Vec<CrawlWarning>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MinimalInstance {
+    pub domain: String,
+    pub version: String,
+    pub total_users: i64,
+    pub users_active_month: i64,
+}
+
+pub fn minimal_instance_data(stats: &TotalStats) -> MinimalStats {
+    MinimalStats {
+        totals: stats.totals(),
+        instances: stats
+            .instance_details
+            .iter()
+            .map(|i| MinimalInstance {
+                domain: i.domain.clone(),
+                version: i.site_info.version(),
+                total_users: i.site_info.total_users(),
+                users_active_month: i.site_info.users_active_month(),
+            })
+            .collect(),
+        warnings: stats.warnings.clone(),
+    }
+}
+
+/// Totals with instance details in the same shape for all API versions
+#[derive(Debug, Serialize)]
+pub struct NormalizedStats {
+    #[serde(flatten)]
+    pub totals: Totals,
+    pub instances: Vec<NormalizedInstance>,
+}
+
+/// Fields which are available from all supported API versions. The original site response is
+/// included as `raw`, for fields which are not covered.
+#[derive(Debug, Serialize)]
+pub struct NormalizedInstance {
+    pub domain: String,
+    /// Tells which shape `raw` has, see `GetSiteResponse::api_version`
+    pub api_version: &'static str,
+    pub version: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub icon: Option<Url>,
+    pub registration_mode: RegistrationMode,
+    pub nsfw_enabled: bool,
+    pub languages: Vec<String>,
+    pub admins: Vec<Url>,
+    pub total_users: i64,
+    pub users_active_day: i64,
+    pub users_active_week: i64,
+    pub users_active_month: i64,
+    pub users_active_halfyear: i64,
+    pub posts: i64,
+    pub comments: i64,
+    pub raw: GetSiteResponse,
+}
+
+pub fn normalized_instance_data(stats: &TotalStats) -> NormalizedStats {
+    NormalizedStats {
+        totals: stats.totals(),
+        instances: stats
+            .instance_details
+            .iter()
+            .map(|i| {
+                let site = &i.site_info;
+                NormalizedInstance {
+                    domain: i.domain.clone(),
+                    api_version: site.api_version(),
+                    version: site.version(),
+                    name: site.name(),
+                    description: site.description(),
+                    icon: site.icon(),
+                    registration_mode: site.registration_mode(),
+                    nsfw_enabled: site.nsfw_enabled(),
+                    languages: site.discussion_languages(),
+                    admins: site.admins(),
+                    total_users: site.total_users(),
+                    users_active_day: site.users_active_day(),
+                    users_active_week: site.users_active_week(),
+                    users_active_month: site.users_active_month(),
+                    users_active_halfyear: site.users_active_half_year(),
+                    posts: site.posts(),
+                    comments: site.comments(),
+                    raw: site.clone(),
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Descriptive texts of an instance, for search and classification
+#[derive(Debug, Serialize)]
+pub struct InstanceText {
+    pub domain: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub sidebar: Option<String>,
+}
+
+pub fn instance_text_data(stats: &TotalStats) -> Vec<InstanceText> {
+    stats
+        .instance_details
+        .iter()
+        .map(|i| InstanceText {
+            domain: i.domain.clone(),
+            name: i.site_info.name(),
+            description: i.site_info.description(),
+            sidebar: i.site_info.sidebar(),
+        })
+        .collect()
+}
+
+/// Community with the domain of its instance
+#[derive(Debug, Serialize)]
+pub struct MinimalCommunity {
+    pub instance: String,
+    pub name: String,
+    pub title: String,
+    pub actor_id: Url,
+    pub nsfw: bool,
+    pub subscribers: i64,
+    pub posts: i64,
+    pub comments: i64,
+    pub users_active_month: i64,
+}
+
+/// Communities of all instances sorted by subscribers, empty if communities weren't crawled
+pub fn minimal_community_data(stats: &TotalStats) -> Vec<MinimalCommunity> {
+    let mut communities: Vec<_> = stats
+        .instance_details
+        .iter()
+        .flat_map(|i| {
+            i.communities
+                .iter()
+                .flatten()
+                .map(move |c| MinimalCommunity {
+                    instance: i.domain.clone(),
+                    name: c.community.name.clone(),
+                    title: c.community.title.clone(),
+                    actor_id: c.community.actor_id.clone(),
+                    nsfw: c.community.nsfw,
+                    subscribers: c.counts.subscribers,
+                    posts: c.counts.posts,
+                    comments: c.counts.comments,
+                    users_active_month: c.counts.users_active_month,
+                })
+        })
+        .collect();
+    communities.sort_by_key(|c| Reverse(c.subscribers));
+    communities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawl::tests::result_json;
+    use serde_json::{json, Value};
+
+    fn result(communities: Option<Value>, requested: bool) -> CrawlResult {
+        let mut json = result_json();
+        json["communities"] = communities.unwrap_or_default();
+        json["communities_requested"] = json!(requested);
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn community_stats(results: Vec<CrawlResult>) -> Option<TotalCommunityStats> {
+        let mut totals = RunningTotals::default();
+        for result in results {
+            totals.add(&CrawlOutcome::Success(Box::new(result)));
+        }
+        totals.community_stats()
+    }
+
+    #[test]
+    fn shallow_instances_are_no_failed_community_crawls() {
+        let community = json!({
+            "community": {
+                "name": "example",
+                "title": "Example",
+                "actor_id": "https://example.com/c/example"
+            },
+            "counts": {"subscribers": 100, "posts": 4, "comments": 8, "users_active_month": 2}
+        });
+        let stats = community_stats(vec![
+            result(Some(json!([community])), true),
+            result(None, true),
+            result(None, false),
+        ])
+        .unwrap();
+        assert_eq!(stats.total_communities, 1);
+        assert_eq!(stats.failed_instances, 1);
+    }
+
+    #[test]
+    fn no_community_stats_without_requested_communities() {
+        assert!(community_stats(vec![result(None, false)]).is_none());
+    }
+}
@@ -0,0 +1,80 @@
+use crate::crawl::CrawlJob;
+use crate::memory::spilled_domains;
+use crate::CrawlOutput;
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often the state of a running crawl is written to the checkpoint file
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// State of an unfinished crawl, so that it can be resumed after an interruption
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Jobs which were queued or running. Some of them may have finished already, those are
+    /// skipped when resuming.
+    pub pending: Vec<PendingJob>,
+    pub output: CrawlOutput,
+    /// Further results which were moved to disk because the memory limit was reached
+    #[serde(default)]
+    pub spill: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingJob {
+    pub domain: String,
+    pub distance: u8,
+    pub priority: u32,
+}
+
+impl From<&CrawlJob> for PendingJob {
+    fn from(job: &CrawlJob) -> Self {
+        PendingJob {
+            domain: job.domain.clone(),
+            distance: job.current_distance,
+            priority: job.priority,
+        }
+    }
+}
+
+impl Checkpoint {
+    /// Returns `None` if there is no checkpoint at the path
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+    }
+
+    /// Write to a temporary file first, so that an interruption while writing doesn't leave a
+    /// corrupt checkpoint
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Domains which were crawled already, successfully or not
+    pub fn crawled_domains(&self) -> Result<HashSet<String>, Error> {
+        let output = &self.output;
+        let mut domains: HashSet<_> = output
+            .results
+            .iter()
+            .map(|r| &r.domain)
+            .chain(output.failures.iter().map(|f| &f.domain))
+            .chain(output.other_software.iter().map(|o| &o.domain))
+            .chain(output.mbin_results.iter().map(|m| &m.domain))
+            .chain(output.private_api.iter().map(|p| &p.domain))
+            .chain(output.opted_out.iter().map(|o| &o.domain))
+            .cloned()
+            .collect();
+        if let Some(spill) = &self.spill {
+            domains.extend(spilled_domains(spill)?);
+        }
+        Ok(domains)
+    }
+}
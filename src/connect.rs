@@ -0,0 +1,78 @@
+use crate::dns::CachingResolver;
+use crate::ratelimit::RateLimiter;
+use anyhow::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::timeout;
+
+/// Opens connections to instances outside of the HTTP client, eg to read certificates. Uses the
+/// same resolver and rate limit as the client. Not available with a proxy, as these connections
+/// would bypass it.
+#[derive(Debug, Clone)]
+pub(crate) struct Connector {
+    resolver: Option<CachingResolver>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Applies to the lookup and the connection separately
+    timeout: Duration,
+}
+
+impl Connector {
+    pub fn new(
+        resolver: Option<CachingResolver>,
+        rate_limiter: Option<Arc<RateLimiter>>,
+        timeout: Duration,
+    ) -> Self {
+        Connector {
+            resolver,
+            rate_limiter,
+            timeout,
+        }
+    }
+
+    /// Addresses of the domain for HTTPS
+    pub async fn lookup(&self, domain: &str) -> Result<Vec<SocketAddr>, Error> {
+        let addrs = match &self.resolver {
+            Some(resolver) => timeout(self.timeout, resolver.lookup(domain))
+                .await??
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, 443))
+                .collect(),
+            None => timeout(self.timeout, lookup_host((domain, 443)))
+                .await??
+                .collect(),
+        };
+        Ok(addrs)
+    }
+
+    /// Connect to the first of the addresses which accepts the connection, once the rate limit
+    /// allows it
+    pub async fn connect(&self, addrs: &[SocketAddr]) -> Result<TcpStream, Error> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.wait().await;
+        }
+        Ok(timeout(self.timeout, TcpStream::connect(addrs)).await??)
+    }
+
+    /// Time limit for each step of a connection
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn connects_with_rate_limit() -> Result<(), Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let rate_limiter = Arc::new(RateLimiter::new(1000.0));
+        let connector = Connector::new(None, Some(rate_limiter), Duration::from_secs(1));
+        let stream = connector.connect(&[listener.local_addr()?]).await?;
+        assert_eq!(stream.peer_addr()?, listener.local_addr()?);
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use crate::structs::{GetSiteResponse, NodeInfo};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Email addresses, excluding Lemmy user and community handles like @user@example.com
+static EMAIL_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:^|[^\w@!.+-])([\w.+-]+@[\w-]+(?:\.[\w-]+)*\.[a-zA-Z]{2,})")
+        .expect("compile email regex")
+});
+
+/// Matrix user ids like @user:example.com
+static MATRIX_ID_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"@[\w.=/-]+:[\w-]+(?:\.[\w-]+)*\.[a-zA-Z]{2,}").expect("compile matrix id regex")
+});
+
+/// Hints how to reach the operators of an instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceContact {
+    pub admins: Vec<Url>,
+    pub matrix_ids: BTreeSet<String>,
+    pub emails: BTreeSet<String>,
+}
+
+/// Collect contact hints from admin profiles, site sidebar and nodeinfo metadata. Returns `None`
+/// if nothing was found.
+pub fn extract_contact(
+    node_info: &NodeInfo,
+    site_info: &GetSiteResponse,
+) -> Option<InstanceContact> {
+    let mut matrix_ids: BTreeSet<String> = site_info.admin_matrix_ids().into_iter().collect();
+    let mut emails = BTreeSet::new();
+
+    if let Some(sidebar) = site_info.sidebar() {
+        matrix_ids.extend(
+            MATRIX_ID_REGEX
+                .find_iter(&sidebar)
+                .map(|m| m.as_str().to_string()),
+        );
+        emails.extend(
+            EMAIL_REGEX
+                .captures_iter(&sidebar)
+                .map(|c| c[1].to_string()),
+        );
+    }
+
+    // Used by some fediverse software, eg `"maintainer": {"name": "..", "email": ".."}`
+    let maintainer_email = node_info
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get("maintainer"))
+        .and_then(|m| m.get("email"))
+        .and_then(|e| e.as_str());
+    if let Some(email) = maintainer_email {
+        emails.insert(email.to_string());
+    }
+
+    let admins = site_info.admins();
+    if admins.is_empty() && matrix_ids.is_empty() && emails.is_empty() {
+        return None;
+    }
+    Some(InstanceContact {
+        admins,
+        matrix_ids,
+        emails,
+    })
+}
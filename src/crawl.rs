@@ -1,49 +1,511 @@
-use crate::structs::{GetFederatedInstancesResponse, GetSiteResponse, NodeInfo};
-use anyhow::{anyhow, Error};
+use crate::connect::Connector;
+use crate::contact::{extract_contact, InstanceContact};
+use crate::derate::ConcurrencyLimiter;
+use crate::diagnostics::{millis, ConnectionTimings, Diagnostics, Health, TimingRecorder};
+use crate::filter::SharedFilter;
+use crate::graph::Centrality;
+use crate::optout::{OptOutMode, RobotsTxt};
+use crate::paths::PathOverrides;
+use crate::policy::DomainPolicy;
+use crate::queue::JobQueue;
+use crate::structs::{
+    parse_federation_states, CommunityView, FederationState, GetFederatedInstancesResponse,
+    GetSiteResponse, ListCommunitiesResponse, MbinInfo, NodeInfo, NodeInfoWellKnown, PieFedSite,
+};
+use crate::tls::{fetch_tls_info, handshake, TlsInfo};
+use crate::topics::{classify, Topic};
+use anyhow::Error;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use reqwest::{Response, StatusCode, Url};
 use reqwest_middleware::ClientWithMiddleware;
 use semver::Version;
-use serde::Serialize;
-use std::collections::HashSet;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::join;
+use tokio::net::lookup_host;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout};
+use tracing::{debug, instrument};
 
-/// Regex to check that a domain is valid
+/// Software names from nodeinfo which are crawled as Lemmy instances, as their API is compatible
+const LEMMY_API_SOFTWARE: [&str; 3] = ["lemmy", "lemmybb", "piefed"];
+
+/// Lemmy API paths which the crawler requests. Instances which disallow any of them for the
+/// crawler in robots.txt opted out.
+const API_PATHS: [&str; 8] = [
+    "/api/v4/site",
+    "/api/v3/site",
+    "/api/alpha/site",
+    "/api/v4/federated_instances",
+    "/api/v3/federated_instances",
+    "/api/alpha/federated_instances",
+    "/api/v3/community/list",
+    "/api/alpha/community/list",
+];
+
+/// Number of communities per page when listing communities, the maximum allowed by Lemmy
+const COMMUNITIES_PAGE_SIZE: u32 = 50;
+
+/// Regex to check that a domain is valid, after it was converted to punycode
 static DOMAIN_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"^([a-z0-9]+(-[a-z0-9]+)*\.)+[a-z]{2,}$").expect("compile domain regex")
+    Regex::new(r"^([a-z0-9]+(-+[a-z0-9]+)*\.)+([a-z]{2,}|xn--[a-z0-9]+(-[a-z0-9]+)*)$")
+        .expect("compile domain regex")
 });
 
 #[derive(new, Debug, Clone)]
 pub struct CrawlJob {
     pub domain: String,
     pub current_distance: u8,
+    /// Jobs with higher priority are crawled first, inherited by discovered instances
+    pub priority: u32,
     params: Arc<CrawlParams>,
+    /// Overrides the client timeout for requests, when retrying a slow instance
+    #[new(default)]
+    timeout: Option<Duration>,
 }
 
-#[derive(new, Debug)]
+#[derive(Debug)]
 pub struct CrawlParams {
-    min_lemmy_version: Version,
-    exclude_domains: HashSet<String>,
-    max_distance: u8,
-    crawled_instances: Mutex<HashSet<String>>,
-    result_sender: UnboundedSender<CrawlResult>,
-    client: ClientWithMiddleware,
+    /// Instances on older versions are rejected, if set
+    pub(crate) min_lemmy_version: Option<Version>,
+    /// Decides which discovered domains are crawled
+    pub(crate) domain_policy: DomainPolicy,
+    pub(crate) max_distance: u8,
+    pub(crate) crawled_instances: Mutex<HashSet<String>>,
+    pub(crate) result_sender: UnboundedSender<CrawlOutcome>,
+    pub(crate) client: ClientWithMiddleware,
+    /// Sends the first request for the site over HTTP/3, if set
+    #[cfg(feature = "http3")]
+    pub(crate) http3_client: Option<ClientWithMiddleware>,
+    /// Include request timings in results
+    pub(crate) diagnostics: bool,
+    /// Record instances running other software instead of treating them as failed
+    pub(crate) count_other_software: bool,
+    /// Crawl Mbin and Kbin instances instead of treating them as failed
+    pub(crate) include_mbin: bool,
+    /// Fetch local communities of each instance, up to this many pages
+    pub(crate) crawl_communities: Option<u32>,
+    /// Instances which were crawled successfully before, to detect repurposed domains
+    pub(crate) known_instances: HashSet<String>,
+    pub(crate) opt_out_mode: OptOutMode,
+    /// Instances which time out are crawled once more with this timeout, if set
+    pub(crate) slow_retry_timeout: Option<Duration>,
+    /// Rejected instances are left out, and their linked instances are not crawled
+    pub(crate) instance_filter: Option<SharedFilter>,
+    pub(crate) path_overrides: PathOverrides,
+    /// Resolve linked domains before queueing them, and record those which don't exist as failed
+    pub(crate) dns_prefilter: bool,
+    /// Crawl `.onion` domains over plain HTTP, through the proxy
+    pub(crate) include_onion: bool,
+    /// Don't fetch robots.txt, so that no instance is treated as opted out
+    pub(crate) ignore_robots: bool,
+    /// Minimum nodeinfo versions of forks, by software name
+    pub(crate) fork_min_versions: HashMap<String, Version>,
+    /// Limits how many instances are fetched at the same time
+    pub(crate) concurrency: ConcurrencyLimiter,
+    /// Read TLS certificates through `connector`
+    pub(crate) collect_tls: bool,
+    /// Tag instances with topics
+    pub(crate) classify_topics: bool,
+    /// Only these instances get their communities fetched, if set
+    pub(crate) deep_instances: Option<HashSet<String>>,
+    /// No communities are fetched after this time, if set
+    pub(crate) deep_crawl_deadline: Option<Instant>,
+    /// Opens separate connections to instances, missing with a proxy
+    pub(crate) connector: Option<Connector>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CrawlResult {
     pub domain: String,
+    /// Name of the software from nodeinfo, eg `lemmy` or `piefed`. `lemmy` in output of older
+    /// crawler versions, which only crawled Lemmy.
+    #[serde(default = "default_software")]
+    pub software: String,
     pub node_info: NodeInfo,
     pub site_info: GetSiteResponse,
-    pub federated_instances: GetFederatedInstancesResponse,
+    /// Missing if the instance doesn't expose the endpoint, see `federated_instances_error`
+    pub federated_instances: Option<GetFederatedInstancesResponse>,
+    pub federated_instances_error: Option<String>,
+    pub contact: Option<InstanceContact>,
+    /// ISO 639-3 code of the language detected in site description and sidebar
+    pub detected_language: Option<String>,
+    /// HTTP version which the instance answered the site request with, eg `HTTP/2.0`, or
+    /// `HTTP/3.0` if it supports HTTP/3 and that is enabled. Empty in output of older crawler
+    /// versions.
+    #[serde(default)]
+    pub http_version: String,
+    /// Number of instances which this instance reports as linked
+    pub linked_count: Option<usize>,
+    /// Number of crawled instances which report this instance as linked, filled in after the
+    /// crawl is complete. 0 in output of older crawler versions.
+    #[serde(default)]
+    pub linked_by_count: usize,
+    /// Request timings, only with `--diagnostics`
+    pub diagnostics: Option<Diagnostics>,
+    /// Summary of the requests for nodeinfo, site and federated instances
+    #[serde(default)]
+    pub health: Health,
+    /// Local communities sorted by top all time, only with `--crawl-communities`. Missing if
+    /// they couldn't be listed.
+    pub communities: Option<Vec<CommunityView>>,
+    /// Communities were requested, so that missing communities mean that listing failed. False
+    /// for instances in the shallow crawl tier.
+    #[serde(default)]
+    pub communities_requested: bool,
+    /// Only answered when crawled again with a longer timeout, see `--slow-retry-multiplier`
+    #[serde(default)]
+    pub slow_response: bool,
+    /// Tor onion service, only with `include_onion`
+    #[serde(default)]
+    pub onion: bool,
+    /// Set for software other than Lemmy which has the Lemmy API, eg lemmybb or PieFed
+    #[serde(default)]
+    pub fork: Option<Fork>,
+    /// Outgoing federation to each linked instance, only reported by Lemmy 0.19
+    #[serde(default)]
+    pub federation_states: Vec<FederationState>,
+    /// Certificate details, only with `--collect-tls`. Missing if the certificate couldn't be
+    /// read.
+    #[serde(default)]
+    pub tls: Option<TlsInfo>,
+    /// Topical categories, only with `--classify-topics`
+    #[serde(default)]
+    pub topics: Vec<Topic>,
+    /// Degree and PageRank in the federation graph, only with `--graph-metrics`. Filled in after
+    /// the crawl is complete.
+    #[serde(default)]
+    pub centrality: Option<Centrality>,
+}
+
+fn default_software() -> String {
+    "lemmy".to_string()
+}
+
+/// Lemmy fork or other software with Lemmy API, with its own version numbers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fork {
+    /// Software name from nodeinfo
+    pub name: String,
+    /// Version from nodeinfo, which can differ from the Lemmy API version of the site info
+    pub version: String,
+}
+
+impl CrawlResult {
+    /// Drop optional details which take up a lot of memory
+    pub fn trim(&mut self) {
+        self.communities = None;
+        self.diagnostics = None;
+    }
+
+    /// Set user and activity counts of the instance and its communities below the threshold to 0
+    pub fn suppress_small_counts(&mut self, threshold: i64) {
+        self.node_info.suppress_small_counts(threshold);
+        self.site_info.suppress_small_counts(threshold);
+        for community in self.communities.iter_mut().flatten() {
+            community.counts.suppress_small_counts(threshold);
+        }
+    }
+}
+
+/// Details about an instance which couldn't be crawled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrawlFailure {
+    pub domain: String,
+    pub distance: u8,
+    pub error_kind: CrawlErrorKind,
+    pub message: String,
+    /// Requests before the failure, not reachable if none got a response. Missing in output of
+    /// older crawler versions.
+    #[serde(default)]
+    pub health: Health,
+}
+
+/// Fediverse instance which doesn't run Lemmy, with details from its nodeinfo
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OtherSoftwareInstance {
+    pub domain: String,
+    pub software: String,
+    pub version: String,
+    pub total_users: i64,
+    pub users_active_month: i64,
+    pub users_active_halfyear: i64,
+}
+
+impl OtherSoftwareInstance {
+    fn new(domain: String, node_info: NodeInfo) -> Self {
+        let users = node_info.usage.users;
+        OtherSoftwareInstance {
+            domain,
+            software: node_info.software.name,
+            version: node_info.software.version,
+            total_users: users.total,
+            users_active_month: users.active_month,
+            users_active_halfyear: users.active_halfyear,
+        }
+    }
+
+    /// Set user counts below the threshold to 0
+    pub fn suppress_small_counts(&mut self, threshold: i64) {
+        for count in [
+            &mut self.total_users,
+            &mut self.users_active_month,
+            &mut self.users_active_halfyear,
+        ] {
+            if *count < threshold {
+                *count = 0;
+            }
+        }
+    }
+}
+
+/// Mbin or Kbin instance, which federates with Lemmy but has a different API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MbinCrawlResult {
+    pub domain: String,
+    pub node_info: NodeInfo,
+    pub info: MbinInfo,
+}
+
+impl MbinCrawlResult {
+    /// Set user and activity counts below the threshold to 0
+    pub fn suppress_small_counts(&mut self, threshold: i64) {
+        self.node_info.suppress_small_counts(threshold);
+    }
+}
+
+/// Instance which requires login to access the API, so only nodeinfo is available
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrivateApiInstance {
+    pub domain: String,
+    pub node_info: NodeInfo,
+}
+
+/// Instance which opted out of detailed data collection in its robots.txt, so only user counts
+/// are recorded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OptedOutInstance {
+    pub domain: String,
+    pub total_users: i64,
+    pub users_active_month: i64,
+    pub users_active_halfyear: i64,
+}
+
+impl OptedOutInstance {
+    fn new(domain: String, node_info: NodeInfo) -> Self {
+        let users = node_info.usage.users;
+        OptedOutInstance {
+            domain,
+            total_users: users.total,
+            users_active_month: users.active_month,
+            users_active_halfyear: users.active_halfyear,
+        }
+    }
+
+    /// Set user counts below the threshold to 0
+    pub fn suppress_small_counts(&mut self, threshold: i64) {
+        for count in [
+            &mut self.total_users,
+            &mut self.users_active_month,
+            &mut self.users_active_halfyear,
+        ] {
+            if *count < threshold {
+                *count = 0;
+            }
+        }
+    }
+}
+
+/// Why linked domains were not crawled, with the number of links for each reason. Explains
+/// changes of the instance count between runs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DiscoveryReport {
+    /// Links to instances which were crawled before in this run
+    pub already_crawled: usize,
+    /// Links rejected by the exclude list or the domain policy
+    pub excluded: usize,
+    /// Links to domains which are not valid
+    pub invalid: usize,
+    pub excluded_domains: BTreeSet<String>,
+    pub invalid_domains: BTreeSet<String>,
+}
+
+impl DiscoveryReport {
+    pub(crate) fn merge(&mut self, other: DiscoveryReport) {
+        self.already_crawled += other.already_crawled;
+        self.excluded += other.excluded;
+        self.invalid += other.invalid;
+        self.excluded_domains.extend(other.excluded_domains);
+        self.invalid_domains.extend(other.invalid_domains);
+    }
+}
+
+/// Non-fatal issue with the data of an instance, so that consumers can assess data quality
+/// without reading logs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrawlWarning {
+    pub domain: String,
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// Site info only deserialized with the types of an older Lemmy version
+    VersionFallback,
+    /// User counts from nodeinfo and site info disagree
+    CountMismatch,
+    /// List was cut off at a configured limit
+    Truncated,
+}
+
+#[derive(Debug)]
+pub enum CrawlOutcome {
+    Success(Box<CrawlResult>),
+    Failure(CrawlFailure),
+    OtherSoftware(OtherSoftwareInstance),
+    Mbin(Box<MbinCrawlResult>),
+    PrivateApi(Box<PrivateApiInstance>),
+    OptedOut(OptedOutInstance),
+    Warning(CrawlWarning),
+    /// Linked domains of one instance which were not queued
+    Discovery(DiscoveryReport),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrawlErrorKind {
+    Timeout,
+    Connection,
+    InvalidResponse,
+    WrongSoftware,
+    WrongDomain,
+    OutdatedVersion,
+    /// Previously known Lemmy instance, which now serves a different site or software
+    Repurposed,
+    /// Domain doesn't exist, only detected with the DNS prefilter
+    DomainNotFound,
+    Other,
+}
+
+impl CrawlErrorKind {
+    fn error(self, message: String) -> Error {
+        CrawlError {
+            kind: self,
+            message,
+        }
+        .into()
+    }
+
+    /// Determine the kind of a crawl error, based on its underlying type
+    fn from_error(error: &Error) -> Self {
+        if let Some(e) = error.downcast_ref::<CrawlError>() {
+            return e.kind;
+        }
+        match reqwest_error(error) {
+            Some(e) if e.is_timeout() => CrawlErrorKind::Timeout,
+            Some(e) if e.is_decode() => CrawlErrorKind::InvalidResponse,
+            Some(e) if e.is_connect() || e.is_request() => CrawlErrorKind::Connection,
+            Some(_) => CrawlErrorKind::Other,
+            None if error.is::<semver::Error>() || error.is::<serde_json::Error>() => {
+                CrawlErrorKind::InvalidResponse
+            }
+            None => CrawlErrorKind::Other,
+        }
+    }
+
+    /// Whether the domain successfully answered with something other than Lemmy, eg a parking
+    /// page or other software, as opposed to being down or returning errors
+    fn indicates_other_site(self) -> bool {
+        self == CrawlErrorKind::WrongSoftware
+    }
+}
+
+fn reqwest_error(error: &Error) -> Option<&reqwest::Error> {
+    match error.downcast_ref::<reqwest_middleware::Error>() {
+        Some(reqwest_middleware::Error::Reqwest(e)) => Some(e),
+        _ => error.downcast_ref::<reqwest::Error>(),
+    }
+}
+
+/// Error for instances which were reachable, but are rejected by the crawler
+#[derive(Debug)]
+struct CrawlError {
+    kind: CrawlErrorKind,
+    message: String,
+}
+
+impl Display for CrawlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CrawlError {}
+
+/// Returned for instances which were rejected by the instance filter. They are left out of the
+/// output entirely, instead of being recorded as failures.
+#[derive(Debug)]
+struct FilteredOut;
+
+impl Display for FilteredOut {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("rejected by instance filter")
+    }
+}
+
+impl std::error::Error for FilteredOut {}
+
+/// Detect the primary language of the site from its description and sidebar. Only returns
+/// reliable detections, which need a reasonable amount of text.
+fn detect_language(site_info: &GetSiteResponse) -> Option<String> {
+    let text = site_info
+        .description()
+        .into_iter()
+        .chain(site_info.sidebar())
+        .collect::<Vec<_>>()
+        .join("\n");
+    whatlang::detect(&text)
+        .filter(|info| info.is_reliable())
+        .map(|info| info.lang().code().to_string())
+}
+
+/// Data fetched from a single instance
+enum FetchedInstance {
+    Lemmy(Box<InstanceDetails>),
+    /// Only with `include_mbin`
+    Mbin(NodeInfo),
+    /// Only with `count_other_software`
+    OtherSoftware(NodeInfo),
+    /// Site endpoint responded with 401 or 403
+    PrivateApi(NodeInfo),
+    /// Disallowed the crawler in robots.txt
+    OptedOut(NodeInfo),
+}
+
+struct InstanceDetails {
+    node_info: NodeInfo,
+    site_info: GetSiteResponse,
+    /// May fail separately, as some instances disable this endpoint
+    federated_instances: Result<GetFederatedInstancesResponse, Error>,
+    http_version: String,
+    diagnostics: Diagnostics,
+    warnings: Vec<CrawlWarning>,
+    federation_states: Vec<FederationState>,
 }
 
 impl CrawlJob {
-    // TODO: return an enum for crawl states,
-    pub async fn crawl(self, sender: UnboundedSender<CrawlJob>) -> Result<(), Error> {
+    #[instrument(skip_all, fields(domain = %self.domain, distance = self.current_distance))]
+    pub async fn crawl(self, queue: &JobQueue) -> Result<(), Error> {
         // need to acquire and release mutex before recursing, otherwise it will deadlock
         {
             let mut crawled_instances = self.params.crawled_instances.lock().await;
@@ -56,101 +518,851 @@ impl CrawlJob {
             }
         }
 
-        let (node_info, site_info, federated_instances) = self.fetch_instance_details().await?;
+        let known = self.params.known_instances.contains(&self.domain);
+        let timings = TimingRecorder::default();
+        let mut outcome = self.crawl_instance(queue, &timings).await;
+        let mut health = timings.health();
+        if let (Err(e), Some(timeout)) = (&outcome, self.params.slow_retry_timeout) {
+            if CrawlErrorKind::from_error(e) == CrawlErrorKind::Timeout {
+                debug!("Crawling {} again with timeout {timeout:?}", self.domain);
+                let slow_job = CrawlJob {
+                    timeout: Some(timeout),
+                    ..self.clone()
+                };
+                let timings = TimingRecorder::default();
+                outcome = slow_job.crawl_instance(queue, &timings).await;
+                health = timings.health();
+                if let Ok(CrawlOutcome::Success(result)) = &mut outcome {
+                    result.slow_response = true;
+                }
+            }
+        }
+        match outcome {
+            Ok(CrawlOutcome::OtherSoftware(other)) if known => {
+                let message = format!("Previously Lemmy, now runs {}", other.software);
+                self.send_failure(CrawlErrorKind::Repurposed, message, health);
+                Ok(())
+            }
+            Ok(CrawlOutcome::Mbin(mbin)) if known => {
+                let message = format!(
+                    "Previously Lemmy, now runs {}",
+                    mbin.node_info.software.name
+                );
+                self.send_failure(CrawlErrorKind::Repurposed, message, health);
+                Ok(())
+            }
+            Ok(CrawlOutcome::OptedOut(_)) if self.params.opt_out_mode == OptOutMode::Skip => Ok(()),
+            Ok(outcome) => {
+                self.params.result_sender.send(outcome).unwrap();
+                Ok(())
+            }
+            Err(e) if e.is::<FilteredOut>() => {
+                debug!("Rejected by instance filter");
+                Ok(())
+            }
+            Err(e) => {
+                let mut error_kind = CrawlErrorKind::from_error(&e);
+                if known && error_kind.indicates_other_site() {
+                    error_kind = CrawlErrorKind::Repurposed;
+                }
+                self.send_failure(error_kind, format!("{e:#}"), health);
+                Err(e)
+            }
+        }
+    }
 
-        let version = Version::parse(&site_info.version())?;
-        if version < self.params.min_lemmy_version {
-            return Err(anyhow!("too old lemmy version {version}"));
+    fn warning(&self, kind: WarningKind, message: String) -> CrawlWarning {
+        CrawlWarning {
+            domain: self.domain.clone(),
+            kind,
+            message,
         }
+    }
 
-        if self.current_distance < self.params.max_distance {
-            let crawled_instances = self.params.crawled_instances.lock().await;
-            federated_instances
-                .federated_instances()
-                .clone()
-                .map(|f| f.linked)
-                .unwrap_or_default()
-                .into_iter()
-                .filter(|i| !self.params.exclude_domains.contains(&i.instance.domain))
-                .filter(|i| !crawled_instances.contains(&i.instance.domain))
-                .filter(|i| DOMAIN_REGEX.is_match(&i.instance.domain))
-                .map(|i| {
-                    CrawlJob::new(
-                        i.instance.domain,
-                        self.current_distance + 1,
-                        self.params.clone(),
-                    )
-                })
-                .for_each(|j| sender.send(j).unwrap());
+    fn send_failure(&self, error_kind: CrawlErrorKind, message: String, health: Health) {
+        let failure = CrawlFailure {
+            domain: self.domain.clone(),
+            distance: self.current_distance,
+            error_kind,
+            message,
+            health,
+        };
+        self.params
+            .result_sender
+            .send(CrawlOutcome::Failure(failure))
+            .unwrap();
+    }
+
+    /// Requests for nodeinfo, site and federated instances are recorded in `timings`
+    async fn crawl_instance(
+        &self,
+        queue: &JobQueue,
+        timings: &TimingRecorder,
+    ) -> Result<CrawlOutcome, Error> {
+        let fetched = {
+            let _permit = self.params.concurrency.acquire().await;
+            self.fetch_instance_details(timings).await
+        };
+        let network_error = fetched.as_ref().err().map_or(false, |e| {
+            matches!(
+                CrawlErrorKind::from_error(e),
+                CrawlErrorKind::Timeout | CrawlErrorKind::Connection
+            )
+        });
+        self.params.concurrency.record(network_error);
+        let details = match fetched? {
+            FetchedInstance::Lemmy(details) => details,
+            FetchedInstance::Mbin(node_info) => {
+                let result = self.crawl_mbin_instance(node_info).await?;
+                return Ok(CrawlOutcome::Mbin(Box::new(result)));
+            }
+            FetchedInstance::OtherSoftware(node_info) => {
+                let other = OtherSoftwareInstance::new(self.domain.clone(), node_info);
+                return Ok(CrawlOutcome::OtherSoftware(other));
+            }
+            FetchedInstance::PrivateApi(node_info) => {
+                let private = PrivateApiInstance {
+                    domain: self.domain.clone(),
+                    node_info,
+                };
+                return Ok(CrawlOutcome::PrivateApi(Box::new(private)));
+            }
+            FetchedInstance::OptedOut(node_info) => {
+                let opted_out = OptedOutInstance::new(self.domain.clone(), node_info);
+                return Ok(CrawlOutcome::OptedOut(opted_out));
+            }
+        };
+        let InstanceDetails {
+            node_info,
+            site_info,
+            federated_instances,
+            http_version,
+            diagnostics,
+            mut warnings,
+            federation_states,
+        } = *details;
+        let (federated_instances, federated_instances_error) = match federated_instances {
+            Ok(f) => (Some(f), None),
+            Err(e) => (None, Some(format!("{e:#}"))),
+        };
+
+        // Forks have their own version numbers and policy
+        let fork = (node_info.software.name != "lemmy").then(|| Fork {
+            name: node_info.software.name.clone(),
+            version: node_info.software.version.clone(),
+        });
+        match &fork {
+            None => {
+                if let Some(min_lemmy_version) = &self.params.min_lemmy_version {
+                    let version = Version::parse(&site_info.version())?;
+                    if &version < min_lemmy_version {
+                        return Err(CrawlErrorKind::OutdatedVersion
+                            .error(format!("too old lemmy version {version}")));
+                    }
+                }
+            }
+            Some(fork) => {
+                if let Some(min_version) = self.params.fork_min_versions.get(&fork.name) {
+                    let version = Version::parse(&fork.version).map_err(|e| {
+                        CrawlErrorKind::OutdatedVersion.error(format!(
+                            "unknown {} version {}: {e}",
+                            fork.name, fork.version
+                        ))
+                    })?;
+                    if &version < min_version {
+                        return Err(CrawlErrorKind::OutdatedVersion
+                            .error(format!("too old {} version {version}", fork.name)));
+                    }
+                }
+            }
         }
 
-        let crawl_result = CrawlResult {
+        let contact = extract_contact(&node_info, &site_info);
+        let detected_language = detect_language(&site_info);
+        let tls = match &self.params.connector {
+            Some(connector) if self.params.collect_tls && !self.is_onion() => {
+                match fetch_tls_info(&self.domain, connector).await {
+                    Ok(tls) => Some(tls),
+                    Err(e) => {
+                        debug!("Failed to read TLS certificate: {e}");
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        let mut result = CrawlResult {
             domain: self.domain.clone(),
+            software: node_info.software.name.clone(),
             node_info,
             site_info,
             federated_instances,
+            federated_instances_error,
+            contact,
+            detected_language,
+            http_version,
+            linked_count: None,
+            linked_by_count: 0,
+            health: diagnostics.health(),
+            diagnostics: self.params.diagnostics.then_some(diagnostics),
+            communities: None,
+            communities_requested: false,
+            slow_response: false,
+            onion: self.is_onion(),
+            fork,
+            federation_states,
+            tls,
+            topics: vec![],
+            centrality: None,
         };
-        self.params.result_sender.send(crawl_result).unwrap();
+        // Communities are not fetched yet at this point
+        if let Some(filter) = &self.params.instance_filter {
+            if !filter.accept(&result) {
+                return Err(FilteredOut.into());
+            }
+        }
 
-        Ok(())
+        if self.current_distance < self.params.max_distance {
+            let linked = result
+                .federated_instances
+                .as_ref()
+                .map(|f| f.linked())
+                .unwrap_or_default();
+            let mut report = DiscoveryReport::default();
+            let mut domains = vec![];
+            let crawled_instances = self.params.crawled_instances.lock().await;
+            for linked in linked {
+                match normalize_domain(&linked).filter(|d| DOMAIN_REGEX.is_match(d)) {
+                    None => {
+                        report.invalid += 1;
+                        report.invalid_domains.insert(linked);
+                    }
+                    Some(domain) if !self.params.domain_policy.allows(&domain) => {
+                        report.excluded += 1;
+                        report.excluded_domains.insert(domain);
+                    }
+                    Some(domain) if crawled_instances.contains(&domain) => {
+                        report.already_crawled += 1;
+                    }
+                    Some(domain) => domains.push(domain),
+                }
+            }
+            // release the lock before waiting for queue capacity, so that other jobs can finish
+            drop(crawled_instances);
+            self.params
+                .result_sender
+                .send(CrawlOutcome::Discovery(report))
+                .unwrap();
+            // unicode and punycode variants of the same domain are identical after normalizing
+            domains.sort_unstable();
+            domains.dedup();
+            if self.params.dns_prefilter {
+                domains = self.remove_nonexistent(domains).await;
+            }
+            for domain in domains {
+                let job = CrawlJob::new(
+                    domain,
+                    self.current_distance + 1,
+                    self.priority,
+                    self.params.clone(),
+                );
+                queue.push_or_wait(job).await;
+            }
+        }
+
+        if let Some(max_pages) = self.params.crawl_communities.filter(|_| self.is_deep()) {
+            let communities = self
+                .fetch_communities(&result.site_info, max_pages)
+                .await
+                .ok();
+            if let Some((_, true)) = communities {
+                let message = format!("Communities limited to {max_pages} pages");
+                warnings.push(self.warning(WarningKind::Truncated, message));
+            }
+            result.communities = communities.map(|(communities, _)| communities);
+            result.communities_requested = true;
+        }
+        // Top communities are used for classification, if they were fetched
+        if self.params.classify_topics {
+            result.topics = classify(&result);
+        }
+        // Only sent for successful crawls, where the data is actually used
+        for warning in warnings {
+            self.params
+                .result_sender
+                .send(CrawlOutcome::Warning(warning))
+                .unwrap();
+        }
+        Ok(CrawlOutcome::Success(Box::new(result)))
+    }
+
+    /// Resolve all domains concurrently, and record those which don't exist as failed instead of
+    /// returning them. This is much faster than failing their HTTP requests, with retries.
+    async fn remove_nonexistent(&self, domains: Vec<String>) -> Vec<String> {
+        let mut lookups = JoinSet::new();
+        let mut existing = vec![];
+        for domain in domains {
+            // Onion services can only be resolved through Tor
+            if self.params.include_onion && is_onion(&domain) {
+                existing.push(domain);
+                continue;
+            }
+            lookups.spawn(async move {
+                let exists = match lookup_host((domain.as_str(), 443)).await {
+                    Ok(_) => true,
+                    Err(e) => !is_nxdomain(&e),
+                };
+                (domain, exists)
+            });
+        }
+        while let Some(lookup) = lookups.join_next().await {
+            let Ok((domain, exists)) = lookup else {
+                continue;
+            };
+            if exists {
+                existing.push(domain);
+                continue;
+            }
+            // May have been crawled through another instance in the meantime
+            if !self
+                .params
+                .crawled_instances
+                .lock()
+                .await
+                .insert(domain.clone())
+            {
+                continue;
+            }
+            let failure = CrawlFailure {
+                domain,
+                distance: self.current_distance + 1,
+                error_kind: CrawlErrorKind::DomainNotFound,
+                message: "domain does not resolve".to_string(),
+                health: Health::default(),
+            };
+            self.params
+                .result_sender
+                .send(CrawlOutcome::Failure(failure))
+                .unwrap();
+        }
+        existing
     }
 
+    /// Returns an error if nodeinfo or site can't be fetched. Failure to fetch federated
+    /// instances is returned separately, as some instances disable that endpoint.
     async fn fetch_instance_details(
         &self,
-    ) -> Result<(NodeInfo, GetSiteResponse, GetFederatedInstancesResponse), Error> {
+        timings: &TimingRecorder,
+    ) -> Result<FetchedInstance, Error> {
+        // Read before any API request, so that instances which opted out don't get any
+        let opted_out = if self.params.ignore_robots {
+            false
+        } else {
+            let robots_txt = self.fetch_robots_txt(timings).await;
+            API_PATHS.iter().any(|path| !robots_txt.allows(path))
+        };
         // Lemmy 0.19.4 switched from nodeinfo 2.0 to 2.1 so we try both endpoints.
         // Otherwise we would have to get the correct url from .well-known, which would
         // require a separate request that can't be parallelized.
-        let node_info_20 = self
-            .params
-            .client
-            .get(format!("https://{}/nodeinfo/2.0.json", &self.domain))
-            .send();
-        let node_info_21 = self
-            .params
-            .client
-            .get(format!("https://{}/nodeinfo/2.1", &self.domain))
-            .send();
-        let site_info = self
-            .params
-            .client
-            .get(format!("https://{}/api/v3/site", &self.domain))
-            .send();
-        let federated_instances = self
-            .params
-            .client
-            .get(format!(
-                "https://{}/api/v3/federated_instances",
-                &self.domain
-            ))
-            .send();
-
-        let (node_info_20, node_info_21, site_info, federated_instances) =
-            join!(node_info_20, node_info_21, site_info, federated_instances);
-
-        let node_info = if let Ok(node_info) = node_info_20?.json::<NodeInfo>().await {
-            node_info
-        } else {
-            node_info_21?.json::<NodeInfo>().await?
+        let node_info_20 = self.get_optional("nodeinfo/2.0.json", timings);
+        let node_info_21 = self.get_optional("nodeinfo/2.1", timings);
+        // Api v4 is available since Lemmy 0.20. Api v3 is only requested if it is missing.
+        let site_info_v4 = async {
+            if opted_out {
+                None
+            } else {
+                Some(self.get_site("api/v4/site", timings, true).await)
+            }
+        };
+        let connection_timings = self.time_connection();
+
+        let (node_info_20, node_info_21, site_info_v4, connection_timings) =
+            join!(node_info_20, node_info_21, site_info_v4, connection_timings);
+
+        let node_info = match timings.json::<NodeInfo>(node_info_20?).await {
+            Ok(node_info) => node_info,
+            Err(_) => match timings.json::<NodeInfo>(node_info_21?).await {
+                Ok(node_info) => node_info,
+                Err(_) => self.fetch_node_info_well_known(timings).await?,
+            },
         };
-        if node_info.software.name != "lemmy" && node_info.software.name != "lemmybb" {
-            return Err(anyhow!("wrong software {}", node_info.software.name));
+        let software = node_info.software.name.as_str();
+        if self.params.include_mbin && (software == "mbin" || software == "kbin") {
+            return Ok(FetchedInstance::Mbin(node_info));
+        }
+        if !LEMMY_API_SOFTWARE.contains(&software) {
+            if self.params.count_other_software {
+                return Ok(FetchedInstance::OtherSoftware(node_info));
+            }
+            return Err(CrawlErrorKind::WrongSoftware
+                .error(format!("wrong software {}", node_info.software.name)));
         }
+        let Some(site_info_v4) = site_info_v4 else {
+            return Ok(FetchedInstance::OptedOut(node_info));
+        };
 
-        let site_info = site_info?.json::<GetSiteResponse>().await?;
+        let piefed = software == "piefed";
+        let api_v4 = !piefed && !is_not_found(&site_info_v4);
+        let site_info = if piefed {
+            // Only requested for PieFed, to avoid useless requests to all Lemmy instances
+            self.get_site("api/alpha/site", timings, false).await
+        } else if api_v4 {
+            site_info_v4
+        } else {
+            self.get_site("api/v3/site", timings, false).await
+        };
+        if is_unauthorized(&site_info) {
+            return Ok(FetchedInstance::PrivateApi(node_info));
+        }
+        let (site_info, http_version) = parse_response::<Value>(site_info, timings).await?;
+        let mut warnings = vec![];
+        let site_info = if piefed {
+            GetSiteResponse::PieFed(PieFedSite {
+                piefed: serde_json::from_value(site_info)?,
+            })
+        } else if api_v4 {
+            GetSiteResponse::V020(serde_json::from_value(site_info)?)
+        } else {
+            // Lemmy 0.18 uses the same endpoint, but its response doesn't deserialize with the
+            // 0.19 types
+            match serde_json::from_value(site_info.clone()) {
+                Ok(site_info) => GetSiteResponse::V019(site_info),
+                Err(_) => {
+                    let message = "Site info parsed as Lemmy 0.18".to_string();
+                    warnings.push(self.warning(WarningKind::VersionFallback, message));
+                    GetSiteResponse::V018(serde_json::from_value(site_info)?)
+                }
+            }
+        };
         let site_actor = site_info.actor_id();
         if site_actor.domain() != Some(&self.domain) {
-            return Err(anyhow!(
+            return Err(CrawlErrorKind::WrongDomain.error(format!(
+                "wrong domain {}, expected {}",
+                site_actor, &self.domain
+            )));
+        }
+
+        // Requested from the same api version as the site info
+        let path = match site_info {
+            GetSiteResponse::PieFed(_) => "api/alpha/federated_instances",
+            GetSiteResponse::V020(_) => "api/v4/federated_instances",
+            GetSiteResponse::V019(_) | GetSiteResponse::V018(_) => "api/v3/federated_instances",
+        };
+        let federated_instances = self.get(path, timings).await;
+        let mut federation_states = vec![];
+        let federated_instances = parse_response::<Value>(federated_instances, timings)
+            .await
+            .and_then(|(f, _)| {
+                Ok(match site_info {
+                    GetSiteResponse::V019(_) => {
+                        federation_states = parse_federation_states(&f);
+                        GetFederatedInstancesResponse::V019(serde_json::from_value(f)?)
+                    }
+                    GetSiteResponse::V020(_) => {
+                        GetFederatedInstancesResponse::V020(serde_json::from_value(f)?)
+                    }
+                    GetSiteResponse::V018(_) => {
+                        GetFederatedInstancesResponse::V018(serde_json::from_value(f)?)
+                    }
+                    GetSiteResponse::PieFed(_) => serde_json::from_value(f)?,
+                })
+            });
+
+        Ok(FetchedInstance::Lemmy(Box::new(InstanceDetails {
+            node_info,
+            site_info,
+            federated_instances,
+            http_version,
+            diagnostics: timings.diagnostics(connection_timings),
+            warnings,
+            federation_states,
+        })))
+    }
+
+    /// Page through the local communities, until an empty page or `max_pages` is reached. Also
+    /// returns if there are more communities than fit in `max_pages`.
+    async fn fetch_communities(
+        &self,
+        site_info: &GetSiteResponse,
+        max_pages: u32,
+    ) -> Result<(Vec<CommunityView>, bool), Error> {
+        let api = match site_info {
+            GetSiteResponse::PieFed(_) => "api/alpha",
+            _ => "api/v3",
+        };
+        let timings = TimingRecorder::default();
+        let mut communities = vec![];
+        // Stay below the rate limit which the instance advertises
+        let interval = site_info.request_interval();
+        for page in 1..=max_pages {
+            if let Some(interval) = interval.filter(|_| page > 1) {
+                sleep(interval).await;
+            }
+            let query =
+                format!("type_=Local&sort=TopAll&limit={COMMUNITIES_PAGE_SIZE}&page={page}");
+            let response = self
+                .get(&format!("{api}/community/list?{query}"), &timings)
+                .await;
+            let (response, _) =
+                parse_response::<ListCommunitiesResponse>(response, &timings).await?;
+            let last_page = response.communities.len() < COMMUNITIES_PAGE_SIZE as usize;
+            communities.extend(response.communities);
+            if last_page {
+                return Ok((communities, false));
+            }
+        }
+        // The last page was full, so check if there is a single community after it. The list is
+        // kept if this check fails.
+        if let Some(interval) = interval {
+            sleep(interval).await;
+        }
+        let page = max_pages * COMMUNITIES_PAGE_SIZE + 1;
+        let response = self
+            .get(
+                &format!("{api}/community/list?type_=Local&sort=TopAll&limit=1&page={page}"),
+                &timings,
+            )
+            .await;
+        let truncated = parse_response::<ListCommunitiesResponse>(response, &timings)
+            .await
+            .is_ok_and(|(response, _)| !response.communities.is_empty());
+        Ok((communities, truncated))
+    }
+
+    /// Mbin doesn't expose federated instances in a form that could be used to continue the
+    /// crawl, so only its details are fetched.
+    async fn crawl_mbin_instance(&self, node_info: NodeInfo) -> Result<MbinCrawlResult, Error> {
+        let timings = TimingRecorder::default();
+        let info = self.get("api/info", &timings).await;
+        let (info, _) = parse_response::<MbinInfo>(info, &timings).await?;
+        if info.website_domain != self.domain {
+            return Err(CrawlErrorKind::WrongDomain.error(format!(
                 "wrong domain {}, expected {}",
-                site_actor,
-                &self.domain
-            ));
+                info.website_domain, &self.domain
+            )));
+        }
+        Ok(MbinCrawlResult {
+            domain: self.domain.clone(),
+            node_info,
+            info,
+        })
+    }
+
+    /// Find the nodeinfo url via `/.well-known/nodeinfo` and fetch it. Slower as it needs two
+    /// sequential requests, so only used if the standard paths fail.
+    async fn fetch_node_info_well_known(
+        &self,
+        timings: &TimingRecorder,
+    ) -> Result<NodeInfo, Error> {
+        let well_known = self
+            .get(".well-known/nodeinfo", timings)
+            .await?
+            .error_for_status()?;
+        let well_known = timings.text(well_known).await?;
+        // A successful response which isn't nodeinfo comes from other software or a parking page
+        let well_known: NodeInfoWellKnown = serde_json::from_str(&well_known).map_err(|e| {
+            CrawlErrorKind::WrongSoftware.error(format!("no nodeinfo in .well-known: {e}"))
+        })?;
+        let href = well_known.newest().ok_or_else(|| {
+            CrawlErrorKind::InvalidResponse.error("no nodeinfo link in .well-known".to_string())
+        })?;
+        let node_info = timings
+            .get(&self.params.client, href.clone(), self.timeout)
+            .await;
+        let (node_info, _) = parse_response(node_info, timings).await?;
+        Ok(node_info)
+    }
+
+    /// Missing or unreadable robots.txt allows everything
+    async fn fetch_robots_txt(&self, timings: &TimingRecorder) -> RobotsTxt {
+        match self
+            .get_optional("robots.txt", timings)
+            .await
+            .map(Response::error_for_status)
+        {
+            Ok(Ok(response)) => timings
+                .text(response)
+                .await
+                .map(|text| RobotsTxt::parse(&text))
+                .unwrap_or_default(),
+            _ => RobotsTxt::default(),
+        }
+    }
+
+    async fn get(
+        &self,
+        path: &str,
+        timings: &TimingRecorder,
+    ) -> reqwest_middleware::Result<Response> {
+        timings
+            .get(&self.params.client, self.url(path)?, self.timeout)
+            .await
+    }
+
+    /// For fallbacks and optional files, where not found isn't an error of the instance
+    async fn get_optional(
+        &self,
+        path: &str,
+        timings: &TimingRecorder,
+    ) -> reqwest_middleware::Result<Response> {
+        timings
+            .get_optional(&self.params.client, self.url(path)?, self.timeout)
+            .await
+    }
+
+    /// Same as `get` or `get_optional`, but tries HTTP/3 first if enabled, so that the HTTP
+    /// version of the site response tells which instances support it
+    async fn get_site(
+        &self,
+        path: &str,
+        timings: &TimingRecorder,
+        optional: bool,
+    ) -> reqwest_middleware::Result<Response> {
+        #[cfg(feature = "http3")]
+        if let Some(client) = &self.params.http3_client {
+            match timings
+                .get_http3(client, self.url(path)?, self.timeout, optional)
+                .await
+            {
+                Ok(response) => return Ok(response),
+                Err(e) => debug!(domain = %self.domain, error = %e, "HTTP/3 request failed"),
+            }
+        }
+        if optional {
+            self.get_optional(path, timings).await
+        } else {
+            self.get(path, timings).await
+        }
+    }
+
+    fn url(&self, path: &str) -> reqwest_middleware::Result<Url> {
+        let path = self.params.path_overrides.resolve(&self.domain, path);
+        // Onion services are already encrypted by Tor, and rarely have a certificate
+        let scheme = if self.is_onion() { "http" } else { "https" };
+        let url = format!("{scheme}://{}/{path}", &self.domain);
+        Url::parse(&url).map_err(|e| reqwest_middleware::Error::Middleware(e.into()))
+    }
+
+    /// Whether the instance is in the deep crawl tier, so that its communities are fetched as
+    /// well. Once the deep crawl budget is used up, all remaining instances are crawled shallow.
+    fn is_deep(&self) -> bool {
+        let in_tier = self
+            .params
+            .deep_instances
+            .as_ref()
+            .map_or(true, |deep| deep.contains(&self.domain));
+        let in_budget = self
+            .params
+            .deep_crawl_deadline
+            .map_or(true, |deadline| Instant::now() < deadline);
+        in_tier && in_budget
+    }
+
+    /// Whether the domain is crawled as onion service
+    fn is_onion(&self) -> bool {
+        self.params.include_onion && is_onion(&self.domain)
+    }
+
+    /// Time the setup of a separate connection to the instance, step by step. Only measured with
+    /// diagnostics enabled, and without a proxy.
+    async fn time_connection(&self) -> ConnectionTimings {
+        let mut timings = ConnectionTimings::default();
+        let Some(connector) = &self.params.connector else {
+            return timings;
+        };
+        if !self.params.diagnostics || self.is_onion() {
+            return timings;
+        }
+        let start = Instant::now();
+        let Ok(addrs) = connector.lookup(&self.domain).await else {
+            return timings;
+        };
+        timings.dns_lookup_ms = Some(millis(start.elapsed()));
+        let start = Instant::now();
+        let Ok(stream) = connector.connect(&addrs).await else {
+            return timings;
+        };
+        timings.connect_ms = Some(millis(start.elapsed()));
+        let start = Instant::now();
+        let handshake = timeout(connector.timeout(), handshake(&self.domain, stream));
+        if let Ok(Ok(())) = handshake.await {
+            timings.tls_handshake_ms = Some(millis(start.elapsed()));
         }
+        timings
+    }
+}
+
+/// Converts the domain to lowercase punycode, so that each instance has only one spelling.
+/// Returns `None` for domains which are not valid.
+pub(crate) fn normalize_domain(domain: &str) -> Option<String> {
+    idna::domain_to_ascii(domain.trim_end_matches('.')).ok()
+}
+
+fn is_onion(domain: &str) -> bool {
+    domain.ends_with(".onion")
+}
+
+/// Whether the lookup failed because the domain doesn't exist, as opposed to a temporary
+/// resolver problem. The standard library only exposes the resolver's error message.
+fn is_nxdomain(error: &std::io::Error) -> bool {
+    let message = error.to_string();
+    [
+        "Name or service not known",
+        "No address associated with hostname",
+        "nodename nor servname provided",
+    ]
+    .iter()
+    .any(|m| message.contains(m))
+}
+
+/// Check if the endpoint doesn't exist, eg api v4 on instances before Lemmy 0.20
+fn is_not_found(response: &reqwest_middleware::Result<Response>) -> bool {
+    matches!(response, Ok(r) if r.status() == StatusCode::NOT_FOUND)
+}
+
+/// Check if the request was rejected because it needs authentication
+fn is_unauthorized(response: &reqwest_middleware::Result<Response>) -> bool {
+    match response {
+        Ok(r) => r.status() == StatusCode::UNAUTHORIZED || r.status() == StatusCode::FORBIDDEN,
+        Err(_) => false,
+    }
+}
+
+/// Deserialize a successful response, and return the HTTP version it was sent with
+async fn parse_response<T: DeserializeOwned>(
+    response: reqwest_middleware::Result<Response>,
+    timings: &TimingRecorder,
+) -> Result<(T, String), Error> {
+    let response = response?.error_for_status()?;
+    let http_version = format!("{:?}", response.version());
+    Ok((timings.json::<T>(response).await?, http_version))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    /// Job with default settings, which can't send requests
+    pub(crate) fn job(domain: &str, distance: u8) -> CrawlJob {
+        let params = CrawlParams {
+            min_lemmy_version: None,
+            domain_policy: DomainPolicy::default(),
+            max_distance: 10,
+            crawled_instances: Mutex::default(),
+            result_sender: unbounded_channel().0,
+            client: reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build(),
+            #[cfg(feature = "http3")]
+            http3_client: None,
+            diagnostics: false,
+            count_other_software: false,
+            include_mbin: false,
+            crawl_communities: None,
+            known_instances: HashSet::new(),
+            opt_out_mode: OptOutMode::Counts,
+            slow_retry_timeout: None,
+            instance_filter: None,
+            path_overrides: PathOverrides::default(),
+            dns_prefilter: false,
+            include_onion: false,
+            ignore_robots: false,
+            fork_min_versions: HashMap::new(),
+            concurrency: ConcurrencyLimiter::new(1, false),
+            collect_tls: false,
+            classify_topics: false,
+            deep_instances: None,
+            deep_crawl_deadline: None,
+            connector: None,
+        };
+        CrawlJob::new(domain.to_string(), distance, 0, Arc::new(params))
+    }
+
+    /// Crawl result of a Lemmy 0.18 instance, with all fields that older crawler versions wrote
+    pub(crate) fn result_json() -> Value {
+        json!({
+            "domain": "example.com",
+            "software": "lemmy",
+            "node_info": {
+                "version": "2.0",
+                "software": {"name": "lemmy", "version": "0.18.5"},
+                "protocols": ["activitypub"],
+                "usage": {"users": {"total": 10, "activeHalfyear": 5, "activeMonth": 3}},
+                "openRegistrations": true
+            },
+            "site_info": {
+                "site_view": {
+                    "site": {"name": "Example", "actor_id": "https://example.com/"},
+                    "local_site": {"registration_mode": "Open"},
+                    "counts": {
+                        "users": 10,
+                        "posts": 20,
+                        "comments": 30,
+                        "users_active_day": 1,
+                        "users_active_week": 2,
+                        "users_active_month": 3,
+                        "users_active_half_year": 5
+                    }
+                },
+                "admins": [],
+                "version": "0.18.5",
+                "all_languages": [],
+                "discussion_languages": []
+            },
+            "http_version": "HTTP/2.0",
+            "linked_by_count": 0
+        })
+    }
+
+    #[test]
+    fn http_version_defaults_to_empty() -> Result<(), Error> {
+        let mut json = result_json();
+        json.as_object_mut().unwrap().remove("http_version");
+        let result: CrawlResult = serde_json::from_value(json)?;
+        assert_eq!(result.http_version, "");
+        Ok(())
+    }
+
+    #[test]
+    fn software_defaults_to_lemmy() -> Result<(), Error> {
+        let mut json = result_json();
+        json.as_object_mut().unwrap().remove("software");
+        let result: CrawlResult = serde_json::from_value(json)?;
+        assert_eq!(result.software, "lemmy");
+        Ok(())
+    }
 
-        let federated_instances = federated_instances?
-            .json::<GetFederatedInstancesResponse>()
-            .await?;
+    #[test]
+    fn suppresses_small_instance_and_community_counts() -> Result<(), Error> {
+        let mut json = result_json();
+        json["communities"] = json!([{
+            "community": {
+                "name": "example",
+                "title": "Example",
+                "actor_id": "https://example.com/c/example"
+            },
+            "counts": {"subscribers": 100, "posts": 4, "comments": 8, "users_active_month": 2}
+        }]);
+        let mut result: CrawlResult = serde_json::from_value(json)?;
+        result.suppress_small_counts(5);
+        assert_eq!(result.site_info.total_users(), 10);
+        assert_eq!(result.site_info.users_active_month(), 0);
+        assert_eq!(result.site_info.users_active_half_year(), 5);
+        assert_eq!(result.node_info.usage.users.active_month, 0);
+        let counts = &result.communities.unwrap()[0].counts;
+        assert_eq!(counts.subscribers, 100);
+        assert_eq!(counts.posts, 0);
+        assert_eq!(counts.comments, 8);
+        assert_eq!(counts.users_active_month, 0);
+        Ok(())
+    }
 
-        Ok((node_info, site_info, federated_instances))
+    #[test]
+    fn linked_by_count_defaults_to_zero() -> Result<(), Error> {
+        let mut json = result_json();
+        json.as_object_mut().unwrap().remove("linked_by_count");
+        let result: CrawlResult = serde_json::from_value(json)?;
+        assert_eq!(result.linked_by_count, 0);
+        Ok(())
     }
 }
@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::{info, warn};
+
+/// Number of recent fetches which the failure rate is calculated from
+const WINDOW: usize = 50;
+
+/// Concurrency is halved once more than this fraction of recent fetches failed with network
+/// errors. Dead instances alone rarely come close.
+const SPIKE_RATIO: f64 = 0.8;
+
+/// Concurrency is increased again while fewer recent fetches than this fail
+const RECOVER_RATIO: f64 = 0.5;
+
+/// Limits the number of instances which are fetched at the same time. When network errors spike
+/// across many instances at once, the cause is more likely local network saturation or upstream
+/// throttling than the instances themselves. Then concurrency is reduced, and increased again
+/// step by step once errors are back to normal.
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    semaphore: Semaphore,
+    max: usize,
+    /// Doesn't change if `None`
+    state: Option<Mutex<LimiterState>>,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    /// Whether each recent fetch failed with a network error
+    recent: VecDeque<bool>,
+    limit: usize,
+    /// Permits which are forgotten once they are released, after the limit was reduced
+    excess: usize,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(max: usize, adaptive: bool) -> Self {
+        ConcurrencyLimiter {
+            semaphore: Semaphore::new(max),
+            max,
+            state: adaptive.then(|| {
+                Mutex::new(LimiterState {
+                    recent: VecDeque::with_capacity(WINDOW),
+                    limit: max,
+                    excess: 0,
+                })
+            }),
+        }
+    }
+
+    pub async fn acquire(&self) -> LimiterPermit<'_> {
+        let permit = self
+            .semaphore
+            .acquire()
+            .await
+            .expect("semaphore is never closed");
+        LimiterPermit {
+            limiter: self,
+            permit: Some(permit),
+        }
+    }
+
+    /// Record the result of a fetch, and adjust the limit after each full window
+    pub fn record(&self, network_error: bool) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        let mut state = state.lock().unwrap();
+        state.recent.push_back(network_error);
+        if state.recent.len() < WINDOW {
+            return;
+        }
+        let failed = state.recent.iter().filter(|f| **f).count();
+        let ratio = failed as f64 / WINDOW as f64;
+        // Measure again with the new limit
+        state.recent.clear();
+        if ratio > SPIKE_RATIO && state.limit > 1 {
+            let limit = state.limit / 2;
+            warn!("{failed} of the last {WINDOW} fetches failed, reducing concurrency to {limit}");
+            state.excess += state.limit - limit;
+            state.limit = limit;
+        } else if ratio < RECOVER_RATIO && state.limit < self.max {
+            let step = (self.max / 10).clamp(1, self.max - state.limit);
+            state.limit += step;
+            info!("Increasing concurrency to {}", state.limit);
+            let reclaimed = step.min(state.excess);
+            state.excess -= reclaimed;
+            self.semaphore.add_permits(step - reclaimed);
+        }
+    }
+}
+
+/// Permit to fetch an instance. Given back to the limiter when dropped, unless the limit was
+/// reduced in the meantime.
+pub struct LimiterPermit<'a> {
+    limiter: &'a ConcurrencyLimiter,
+    permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for LimiterPermit<'_> {
+    fn drop(&mut self) {
+        let Some(state) = &self.limiter.state else {
+            return;
+        };
+        let mut state = state.lock().unwrap();
+        if state.excess > 0 {
+            state.excess -= 1;
+            if let Some(permit) = self.permit.take() {
+                permit.forget();
+            }
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use reqwest::{Response, Url};
+use reqwest_middleware::ClientWithMiddleware;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Timings which help to tell slow instances apart from a slow network
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Diagnostics {
+    /// Time to resolve the domain, missing if the lookup failed
+    pub dns_lookup_ms: Option<u64>,
+    /// Time to open a TCP connection, missing if it failed. reqwest doesn't expose the setup of
+    /// its connections, so this is measured with a separate connection, as is the TLS handshake.
+    #[serde(default)]
+    pub connect_ms: Option<u64>,
+    /// Time of the TLS handshake, missing if it failed
+    #[serde(default)]
+    pub tls_handshake_ms: Option<u64>,
+    pub endpoints: Vec<EndpointTiming>,
+}
+
+/// Setup times of a separate connection to an instance, see `Diagnostics`
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionTimings {
+    pub dns_lookup_ms: Option<u64>,
+    pub connect_ms: Option<u64>,
+    pub tls_handshake_ms: Option<u64>,
+}
+
+/// Timing of a single request. The time to first byte includes connection setup, unless an
+/// existing connection was reused.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointTiming {
+    pub path: String,
+    /// Time until response headers were received, or until the request failed
+    pub time_to_first_byte_ms: u64,
+    /// Missing if the body wasn't read, eg because of an error status
+    pub body_read_ms: Option<u64>,
+    /// HTTP status code, missing if the request failed
+    #[serde(default)]
+    pub status: Option<u16>,
+    /// The request failed during the TLS handshake, eg because of an expired certificate
+    #[serde(default)]
+    pub tls_failed: bool,
+    /// Not found is expected for this path, eg nodeinfo 2.0 on newer Lemmy versions, so it
+    /// doesn't count as error
+    #[serde(default)]
+    pub optional: bool,
+}
+
+/// How responsive an instance was during the crawl, derived from its request timings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Health {
+    /// At least one request got a response
+    pub reachable: bool,
+    /// Average time to first byte of the requests which got a response
+    pub avg_latency_ms: Option<u64>,
+    /// Requests which failed or got an error status, except not found for optional paths
+    pub errors: usize,
+    /// No request failed during the TLS handshake
+    pub tls_ok: bool,
+}
+
+impl Diagnostics {
+    pub fn health(&self) -> Health {
+        let latencies: Vec<_> = self
+            .endpoints
+            .iter()
+            .filter(|e| e.status.is_some())
+            .map(|e| e.time_to_first_byte_ms)
+            .collect();
+        let avg_latency_ms = match latencies.len() {
+            0 => None,
+            n => Some(latencies.iter().sum::<u64>() / n as u64),
+        };
+        Health {
+            reachable: !latencies.is_empty(),
+            avg_latency_ms,
+            errors: self
+                .endpoints
+                .iter()
+                .filter(|e| match e.status {
+                    Some(404) => !e.optional,
+                    Some(status) => status >= 400,
+                    None => true,
+                })
+                .count(),
+            tls_ok: !self.endpoints.iter().any(|e| e.tls_failed),
+        }
+    }
+}
+
+/// Sends requests and reads responses, while recording how long each step takes
+#[derive(Debug, Default)]
+pub(crate) struct TimingRecorder {
+    endpoints: Mutex<Vec<EndpointTiming>>,
+}
+
+impl TimingRecorder {
+    pub async fn get(
+        &self,
+        client: &ClientWithMiddleware,
+        url: Url,
+        timeout: Option<Duration>,
+    ) -> reqwest_middleware::Result<Response> {
+        self.request(client, url, timeout, false).await
+    }
+
+    /// Same as `get`, for paths which many instances don't have, see `EndpointTiming::optional`
+    pub async fn get_optional(
+        &self,
+        client: &ClientWithMiddleware,
+        url: Url,
+        timeout: Option<Duration>,
+    ) -> reqwest_middleware::Result<Response> {
+        self.request(client, url, timeout, true).await
+    }
+
+    async fn request(
+        &self,
+        client: &ClientWithMiddleware,
+        url: Url,
+        timeout: Option<Duration>,
+        optional: bool,
+    ) -> reqwest_middleware::Result<Response> {
+        let path = url.path().to_string();
+        let start = Instant::now();
+        let mut request = client.get(url);
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        let response = request.send().await;
+        self.record(path, start, &response, optional);
+        response
+    }
+
+    /// Same as `get` or `get_optional` over HTTP/3. Only recorded if it gets a response, as the
+    /// caller falls back to the other HTTP versions.
+    #[cfg(feature = "http3")]
+    pub async fn get_http3(
+        &self,
+        client: &ClientWithMiddleware,
+        url: Url,
+        timeout: Option<Duration>,
+        optional: bool,
+    ) -> reqwest_middleware::Result<Response> {
+        let path = url.path().to_string();
+        let start = Instant::now();
+        let mut request = client.get(url).version(reqwest::Version::HTTP_3);
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        let response = request.send().await;
+        if response.is_ok() {
+            self.record(path, start, &response, optional);
+        }
+        response
+    }
+
+    fn record(
+        &self,
+        path: String,
+        start: Instant,
+        response: &reqwest_middleware::Result<Response>,
+        optional: bool,
+    ) {
+        let time_to_first_byte_ms = millis(start.elapsed());
+        let (status, tls_failed) = match response {
+            Ok(r) => {
+                let status = r.status().as_u16();
+                debug!(%path, status, time_to_first_byte_ms, "Response");
+                (Some(status), false)
+            }
+            Err(e) => {
+                debug!(%path, time_to_first_byte_ms, error = %e, "Request failed");
+                (None, is_tls_error(e))
+            }
+        };
+        self.endpoints.lock().unwrap().push(EndpointTiming {
+            path,
+            time_to_first_byte_ms,
+            body_read_ms: None,
+            status,
+            tls_failed,
+            optional,
+        });
+    }
+
+    pub async fn json<T: DeserializeOwned>(&self, response: Response) -> reqwest::Result<T> {
+        let path = response.url().path().to_string();
+        let start = Instant::now();
+        let json = response.json().await;
+        self.record_body_read(&path, millis(start.elapsed()));
+        json
+    }
+
+    pub async fn text(&self, response: Response) -> reqwest::Result<String> {
+        let path = response.url().path().to_string();
+        let start = Instant::now();
+        let text = response.text().await;
+        self.record_body_read(&path, millis(start.elapsed()));
+        text
+    }
+
+    fn record_body_read(&self, path: &str, elapsed: u64) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        if let Some(e) = endpoints
+            .iter_mut()
+            .find(|e| e.path == path && e.body_read_ms.is_none())
+        {
+            e.body_read_ms = Some(elapsed);
+        }
+    }
+
+    /// Health of the requests so far, also for instances which failed
+    pub fn health(&self) -> Health {
+        Diagnostics {
+            endpoints: self.endpoints.lock().unwrap().clone(),
+            ..Default::default()
+        }
+        .health()
+    }
+
+    pub fn diagnostics(&self, connection: ConnectionTimings) -> Diagnostics {
+        let mut endpoints = self.endpoints.lock().unwrap().clone();
+        endpoints.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+        Diagnostics {
+            dns_lookup_ms: connection.dns_lookup_ms,
+            connect_ms: connection.connect_ms,
+            tls_handshake_ms: connection.tls_handshake_ms,
+            endpoints,
+        }
+    }
+}
+
+/// reqwest doesn't expose the kind of connection error, so this checks the messages of the
+/// underlying errors
+fn is_tls_error(error: &reqwest_middleware::Error) -> bool {
+    let mut source: Option<&dyn Error> = Some(error);
+    while let Some(e) = source {
+        let message = e.to_string().to_lowercase();
+        if message.contains("certificate") || message.contains("tls") {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+pub(crate) fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(path: &str, status: Option<u16>, optional: bool) -> EndpointTiming {
+        EndpointTiming {
+            path: path.to_string(),
+            time_to_first_byte_ms: 100,
+            body_read_ms: None,
+            status,
+            tls_failed: false,
+            optional,
+        }
+    }
+
+    #[test]
+    fn not_found_of_optional_paths_is_no_error() {
+        let diagnostics = Diagnostics {
+            endpoints: vec![
+                endpoint("/nodeinfo/2.0.json", Some(404), true),
+                endpoint("/nodeinfo/2.1", Some(200), true),
+                endpoint("/api/v4/site", Some(404), true),
+                endpoint("/api/v3/site", Some(200), false),
+                endpoint("/api/v3/federated_instances", Some(404), false),
+                endpoint("/robots.txt", Some(500), true),
+            ],
+            ..Default::default()
+        };
+        let health = diagnostics.health();
+        assert!(health.reachable);
+        assert_eq!(health.errors, 2);
+        assert_eq!(health.avg_latency_ms, Some(100));
+    }
+
+    #[test]
+    fn failed_requests_are_unreachable() {
+        let recorder = TimingRecorder::default();
+        recorder
+            .endpoints
+            .lock()
+            .unwrap()
+            .push(endpoint("/nodeinfo/2.0.json", None, true));
+        let health = recorder.health();
+        assert!(!health.reachable);
+        assert_eq!(health.errors, 1);
+        assert_eq!(health.avg_latency_ms, None);
+    }
+
+    #[cfg(feature = "http3")]
+    #[tokio::test]
+    async fn failed_http3_attempt_is_not_recorded() {
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        let recorder = TimingRecorder::default();
+        let url = Url::parse("https://127.0.0.1:1/api/v3/site").unwrap();
+        let response = recorder
+            .get_http3(&client, url, Some(Duration::from_secs(1)), false)
+            .await;
+        assert!(response.is_err());
+        assert_eq!(recorder.health().errors, 0);
+    }
+}
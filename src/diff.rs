@@ -0,0 +1,226 @@
+use crate::aggregate::TotalStats;
+use crate::structs::GetSiteResponse;
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Totals and instance versions of a crawl, enough to compare it with a later crawl
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CrawlSummary {
+    pub run_id: String,
+    pub crawled_instances: i64,
+    pub total_users: i64,
+    pub users_active_day: i64,
+    pub users_active_week: i64,
+    pub users_active_month: i64,
+    pub users_active_halfyear: i64,
+    /// Version of each crawled instance by domain
+    pub versions: BTreeMap<String, String>,
+}
+
+impl CrawlSummary {
+    pub fn new(stats: &TotalStats) -> Self {
+        CrawlSummary {
+            run_id: stats.run_id.clone(),
+            crawled_instances: stats.crawled_instances.into(),
+            total_users: stats.total_users,
+            users_active_day: stats.users_active_day,
+            users_active_week: stats.users_active_week,
+            users_active_month: stats.users_active_month,
+            users_active_halfyear: stats.users_active_halfyear,
+            versions: stats
+                .instance_details
+                .iter()
+                .map(|i| (i.domain.clone(), i.site_info.version()))
+                .collect(),
+        }
+    }
+
+    /// Read the summary from JSON output, as returned by `migrate::load_output`
+    pub fn from_output(output: &Value) -> Result<Self, Error> {
+        let total = |field: &str| {
+            output[field]
+                .as_i64()
+                .ok_or_else(|| anyhow!("crawler output is missing {field}"))
+        };
+        let mut versions = BTreeMap::new();
+        for instance in output["instance_details"].as_array().into_iter().flatten() {
+            let domain = instance["domain"]
+                .as_str()
+                .ok_or_else(|| anyhow!("instance without domain in crawler output"))?;
+            let site_info = GetSiteResponse::deserialize(&instance["site_info"])?;
+            versions.insert(domain.to_string(), site_info.version());
+        }
+        Ok(CrawlSummary {
+            // Missing in output of older crawler versions
+            run_id: output["run_id"].as_str().unwrap_or_default().to_string(),
+            crawled_instances: total("crawled_instances")?,
+            total_users: total("total_users")?,
+            users_active_day: total("users_active_day")?,
+            users_active_week: total("users_active_week")?,
+            users_active_month: total("users_active_month")?,
+            users_active_halfyear: total("users_active_halfyear")?,
+            versions,
+        })
+    }
+}
+
+/// Changes between two crawls, eg for webhook receivers which update instance lists
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrawlDiff {
+    pub previous_run_id: String,
+    pub run_id: String,
+    /// Instances which were crawled now, but not in the previous crawl
+    pub new_instances: Vec<String>,
+    /// Instances which were crawled in the previous crawl, but not now
+    pub removed_instances: Vec<String>,
+    /// Change of the network totals, negative if they went down
+    pub total_deltas: TotalDeltas,
+    /// Instances which were crawled both times, with a different version
+    pub version_changes: Vec<VersionChange>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TotalDeltas {
+    pub crawled_instances: i64,
+    pub total_users: i64,
+    pub users_active_day: i64,
+    pub users_active_week: i64,
+    pub users_active_month: i64,
+    pub users_active_halfyear: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionChange {
+    pub domain: String,
+    pub previous: String,
+    pub current: String,
+}
+
+impl CrawlDiff {
+    pub fn new(previous: &CrawlSummary, current: &CrawlSummary) -> Self {
+        let new_instances = current
+            .versions
+            .keys()
+            .filter(|domain| !previous.versions.contains_key(*domain))
+            .cloned()
+            .collect();
+        let removed_instances = previous
+            .versions
+            .keys()
+            .filter(|domain| !current.versions.contains_key(*domain))
+            .cloned()
+            .collect();
+        let version_changes = current
+            .versions
+            .iter()
+            .filter_map(|(domain, version)| {
+                let previous = previous.versions.get(domain)?;
+                (previous != version).then(|| VersionChange {
+                    domain: domain.clone(),
+                    previous: previous.clone(),
+                    current: version.clone(),
+                })
+            })
+            .collect();
+        CrawlDiff {
+            previous_run_id: previous.run_id.clone(),
+            run_id: current.run_id.clone(),
+            new_instances,
+            removed_instances,
+            total_deltas: TotalDeltas {
+                crawled_instances: current.crawled_instances - previous.crawled_instances,
+                total_users: current.total_users - previous.total_users,
+                users_active_day: current.users_active_day - previous.users_active_day,
+                users_active_week: current.users_active_week - previous.users_active_week,
+                users_active_month: current.users_active_month - previous.users_active_month,
+                users_active_halfyear: current.users_active_halfyear
+                    - previous.users_active_halfyear,
+            },
+            version_changes,
+        }
+    }
+
+    /// One line description for chat and email, eg
+    /// `2 new instances, 1 removed, 3 version changes, +120 monthly active users`
+    pub fn summary(&self) -> String {
+        format!(
+            "{} new instances, {} removed, {} version changes, {:+} monthly active users",
+            self.new_instances.len(),
+            self.removed_instances.len(),
+            self.version_changes.len(),
+            self.total_deltas.users_active_month
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn summary(run_id: &str, users_active_month: i64, versions: &[(&str, &str)]) -> CrawlSummary {
+        CrawlSummary {
+            run_id: run_id.to_string(),
+            crawled_instances: versions.len() as i64,
+            users_active_month,
+            versions: versions
+                .iter()
+                .map(|(domain, version)| (domain.to_string(), version.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lists_new_removed_and_upgraded_instances() {
+        let previous = summary(
+            "a",
+            100,
+            &[("lemmy.ml", "0.19.3"), ("old.example", "0.18.5")],
+        );
+        let current = summary(
+            "b",
+            80,
+            &[("lemmy.ml", "0.19.5"), ("new.example", "0.19.5")],
+        );
+        let diff = CrawlDiff::new(&previous, &current);
+        assert_eq!(diff.previous_run_id, "a");
+        assert_eq!(diff.run_id, "b");
+        assert_eq!(diff.new_instances, ["new.example"]);
+        assert_eq!(diff.removed_instances, ["old.example"]);
+        assert_eq!(
+            diff.version_changes,
+            [VersionChange {
+                domain: "lemmy.ml".to_string(),
+                previous: "0.19.3".to_string(),
+                current: "0.19.5".to_string(),
+            }]
+        );
+        assert_eq!(diff.total_deltas.crawled_instances, 0);
+        assert_eq!(diff.total_deltas.users_active_month, -20);
+        assert_eq!(
+            diff.summary(),
+            "1 new instances, 1 removed, 1 version changes, -20 monthly active users"
+        );
+    }
+
+    #[test]
+    fn reads_summary_from_output_without_run_id() {
+        let output = json!({
+            "crawled_instances": 0,
+            "total_users": 10,
+            "users_active_day": 1,
+            "users_active_week": 2,
+            "users_active_month": 3,
+            "users_active_halfyear": 4,
+            "instance_details": [],
+        });
+        let summary = CrawlSummary::from_output(&output).unwrap();
+        assert_eq!(summary.run_id, "");
+        assert_eq!(summary.total_users, 10);
+        assert_eq!(summary.users_active_halfyear, 4);
+        assert!(summary.versions.is_empty());
+    }
+}
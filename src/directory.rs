@@ -0,0 +1,35 @@
+use crate::crawl::CrawlResult;
+use lemmy_api_common_v019::lemmy_db_schema::RegistrationMode;
+use reqwest::Url;
+use serde::Serialize;
+
+/// Public details of a single instance, meant for instance pickers in third-party apps
+#[derive(Debug, Serialize)]
+pub struct InstanceDirectoryEntry {
+    pub domain: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub icon: Option<Url>,
+    pub languages: Vec<String>,
+    pub registration_mode: RegistrationMode,
+}
+
+impl InstanceDirectoryEntry {
+    pub fn new(r: &CrawlResult) -> Self {
+        InstanceDirectoryEntry {
+            domain: r.domain.clone(),
+            name: r.site_info.name(),
+            description: r.site_info.description(),
+            icon: r.site_info.icon(),
+            languages: r.site_info.discussion_languages(),
+            registration_mode: r.site_info.registration_mode(),
+        }
+    }
+}
+
+/// Builds a directory of all crawled instances, sorted by domain.
+pub fn instance_directory(results: &[CrawlResult]) -> Vec<InstanceDirectoryEntry> {
+    let mut directory: Vec<_> = results.iter().map(InstanceDirectoryEntry::new).collect();
+    directory.sort_unstable_by(|a, b| a.domain.cmp(&b.domain));
+    directory
+}
@@ -0,0 +1,88 @@
+use anyhow::{anyhow, Error};
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// DNS server to resolve instance domains with, instead of the system configuration. Either an
+/// IP address with optional port for plain DNS, or `https://` and an IP address for DNS over
+/// HTTPS, eg `https://1.1.1.1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsServer {
+    Plain(SocketAddr),
+    Https(SocketAddr),
+}
+
+impl FromStr for DnsServer {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, https) = match s.strip_prefix("https://") {
+            Some(address) => (address.trim_end_matches('/'), true),
+            None => (s, false),
+        };
+        let default_port = if https { 443 } else { 53 };
+        // The server can't be given by name, as there is nothing to resolve it with yet
+        let address = address
+            .parse::<SocketAddr>()
+            .or_else(|_| {
+                address
+                    .parse::<IpAddr>()
+                    .map(|ip| (ip, default_port).into())
+            })
+            .map_err(|_| anyhow!("invalid DNS server {s}, expected IP address"))?;
+        Ok(if https {
+            DnsServer::Https(address)
+        } else {
+            DnsServer::Plain(address)
+        })
+    }
+}
+
+/// Resolver which caches lookups for their TTL, as many instances are requested repeatedly
+/// during a crawl
+#[derive(Debug, Clone)]
+pub(crate) struct CachingResolver(TokioAsyncResolver);
+
+impl CachingResolver {
+    /// Uses the system configuration if no server is given
+    pub(crate) fn new(server: Option<DnsServer>) -> Result<Self, Error> {
+        let resolver = match server {
+            None => TokioAsyncResolver::tokio_from_system_conf()?,
+            Some(server) => {
+                let servers = match server {
+                    DnsServer::Plain(a) => {
+                        NameServerConfigGroup::from_ips_clear(&[a.ip()], a.port(), true)
+                    }
+                    DnsServer::Https(a) => NameServerConfigGroup::from_ips_https(
+                        &[a.ip()],
+                        a.port(),
+                        a.ip().to_string(),
+                        true,
+                    ),
+                };
+                let config = ResolverConfig::from_parts(None, vec![], servers);
+                TokioAsyncResolver::tokio(config, ResolverOpts::default())
+            }
+        };
+        Ok(CachingResolver(resolver))
+    }
+
+    /// Addresses of the domain, for connections outside of the HTTP client
+    pub(crate) async fn lookup(&self, domain: &str) -> Result<Vec<IpAddr>, Error> {
+        Ok(self.0.lookup_ip(domain).await?.into_iter().collect())
+    }
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.0.clone();
+        Box::pin(async move {
+            let lookup = resolver.lookup_ip(name.as_str()).await?;
+            let addrs: Addrs = Box::new(lookup.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
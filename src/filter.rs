@@ -0,0 +1,41 @@
+use crate::crawl::CrawlResult;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+/// Decides which instances are included in a crawl, see `Crawler::instance_filter`. Implemented
+/// for closures, eg `|i: &CrawlResult| i.site_info.users_active_month() >= 10`.
+pub trait InstanceFilter: Send + Sync {
+    /// Called once nodeinfo, site and federated instances are fetched, but before communities.
+    /// Returning false leaves the instance out of the output, and its linked instances are not
+    /// crawled through it.
+    fn accept(&self, instance: &CrawlResult) -> bool;
+}
+
+impl<F> InstanceFilter for F
+where
+    F: Fn(&CrawlResult) -> bool + Send + Sync,
+{
+    fn accept(&self, instance: &CrawlResult) -> bool {
+        self(instance)
+    }
+}
+
+/// Filter which can be cloned together with the crawler
+#[derive(Clone)]
+pub struct SharedFilter(Arc<dyn InstanceFilter>);
+
+impl SharedFilter {
+    pub(crate) fn new(filter: impl InstanceFilter + 'static) -> Self {
+        SharedFilter(Arc::new(filter))
+    }
+
+    pub(crate) fn accept(&self, instance: &CrawlResult) -> bool {
+        self.0.accept(instance)
+    }
+}
+
+impl Debug for SharedFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("InstanceFilter")
+    }
+}
@@ -0,0 +1,52 @@
+use crate::aggregate::TotalStats;
+use crate::output::InstanceRow;
+use crate::rollup::Sample;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Row of the latest crawl, as a flat object with a timestamp for Grafana JSON datasources
+#[derive(Serialize)]
+pub struct GrafanaInstanceRow<'a> {
+    /// Milliseconds since the Unix epoch, as expected by Grafana
+    pub time: i64,
+    #[serde(flatten)]
+    row: InstanceRow<'a>,
+}
+
+/// Counts of one stored crawl, for time series in Grafana
+#[derive(Debug, Serialize)]
+pub struct GrafanaSampleRow {
+    pub time: i64,
+    /// Instance domain, or `total` for the network totals
+    pub instance: String,
+    pub total_users: i64,
+    pub users_active_month: i64,
+}
+
+/// One row per instance of the crawl, all with the time when the crawl finished
+pub fn instance_rows(stats: &TotalStats, crawled_at: DateTime<Utc>) -> Vec<GrafanaInstanceRow<'_>> {
+    stats
+        .instance_details
+        .iter()
+        .map(|i| GrafanaInstanceRow {
+            time: crawled_at.timestamp_millis(),
+            row: InstanceRow::new(i),
+        })
+        .collect()
+}
+
+/// Rows of stored crawls, oldest first. Only the network totals if no domain is given.
+pub fn sample_rows(samples: Vec<Sample>, domain: Option<&str>) -> Vec<GrafanaSampleRow> {
+    let mut rows: Vec<_> = samples
+        .into_iter()
+        .filter(|s| s.domain.as_deref() == domain)
+        .map(|s| GrafanaSampleRow {
+            time: s.crawled_at.timestamp_millis(),
+            instance: s.domain.unwrap_or_else(|| "total".to_string()),
+            total_users: s.total_users,
+            users_active_month: s.users_active_month,
+        })
+        .collect();
+    rows.sort_by_key(|r| r.time);
+    rows
+}
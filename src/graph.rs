@@ -0,0 +1,609 @@
+use crate::crawl::CrawlResult;
+use crate::policy::DomainPolicy;
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+use std::fs;
+use std::iter;
+use std::path::Path;
+
+/// PageRank damping factor, the probability of following a link instead of jumping to a random
+/// instance
+const PAGERANK_DAMPING: f64 = 0.85;
+
+const PAGERANK_ITERATIONS: usize = 100;
+
+/// PageRank stops early once the ranks change less than this in total
+const PAGERANK_TOLERANCE: f64 = 1e-9;
+
+/// Federation relations between crawled instances
+#[derive(Debug)]
+pub struct FederationGraph {
+    /// Domains of crawled instances, sorted
+    pub nodes: Vec<String>,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Debug)]
+pub struct Edge {
+    pub source: String,
+    pub target: String,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeKind {
+    Linked,
+    Allowed,
+    Blocked,
+}
+
+impl EdgeKind {
+    fn name(self) -> &'static str {
+        match self {
+            EdgeKind::Linked => "linked",
+            EdgeKind::Allowed => "allowed",
+            EdgeKind::Blocked => "blocked",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            EdgeKind::Linked => "gray",
+            EdgeKind::Allowed => "green",
+            EdgeKind::Blocked => "red",
+        }
+    }
+
+    /// Value in sparse matrix exports, combined for instances with several relations
+    fn flag(self) -> u8 {
+        match self {
+            EdgeKind::Linked => 1,
+            EdgeKind::Allowed => 2,
+            EdgeKind::Blocked => 4,
+        }
+    }
+}
+
+impl FederationGraph {
+    /// Build the graph from linked, allowed and blocked instances of each crawl result. Only
+    /// edges between crawled instances are included, as linked lists also contain the rest of
+    /// the fediverse.
+    pub fn new(results: &[CrawlResult]) -> Self {
+        let nodes: BTreeSet<&str> = results.iter().map(|r| r.domain.as_str()).collect();
+        let mut edges = vec![];
+        for r in results {
+            let Some(federated_instances) = &r.federated_instances else {
+                continue;
+            };
+            let relations = [
+                (EdgeKind::Linked, federated_instances.linked()),
+                (EdgeKind::Allowed, federated_instances.allowed()),
+                (EdgeKind::Blocked, federated_instances.blocked()),
+            ];
+            for (kind, targets) in relations {
+                edges.extend(
+                    targets
+                        .into_iter()
+                        .filter(|t| t != &r.domain && nodes.contains(t.as_str()))
+                        .map(|target| Edge {
+                            source: r.domain.clone(),
+                            target,
+                            kind,
+                        }),
+                );
+            }
+        }
+        FederationGraph {
+            nodes: nodes.into_iter().map(str::to_string).collect(),
+            edges,
+        }
+    }
+
+    /// Build the graph from a stored JSON crawl output, after migrating it to the current schema
+    pub fn from_output(output: &Value) -> Self {
+        let instances = output["instance_details"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let nodes: BTreeSet<&str> = instances
+            .iter()
+            .filter_map(|i| i["domain"].as_str())
+            .collect();
+        let mut edges = vec![];
+        for i in instances {
+            let Some(source) = i["domain"].as_str() else {
+                continue;
+            };
+            // Instance domains are at the same place for all Lemmy versions
+            let federated_instances = &i["federated_instances"]["federated_instances"];
+            let relations = [
+                (EdgeKind::Linked, &federated_instances["linked"]),
+                (EdgeKind::Allowed, &federated_instances["allowed"]),
+                (EdgeKind::Blocked, &federated_instances["blocked"]),
+            ];
+            for (kind, targets) in relations {
+                edges.extend(
+                    targets
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|t| t["domain"].as_str())
+                        .filter(|t| *t != source && nodes.contains(t))
+                        .map(|target| Edge {
+                            source: source.to_string(),
+                            target: target.to_string(),
+                            kind,
+                        }),
+                );
+            }
+        }
+        FederationGraph {
+            nodes: nodes.into_iter().map(str::to_string).collect(),
+            edges,
+        }
+    }
+
+    /// Determine which instances a crawl with the given settings would reach, by following
+    /// linked edges the same way as the crawler does. No network access is needed.
+    pub fn simulate(
+        &self,
+        start_instances: &[String],
+        domain_policy: &DomainPolicy,
+        max_distance: u8,
+    ) -> Simulation {
+        let mut linked: HashMap<&str, Vec<&str>> = HashMap::new();
+        for e in self.edges.iter().filter(|e| e.kind == EdgeKind::Linked) {
+            linked.entry(&e.source).or_default().push(&e.target);
+        }
+        let nodes: HashSet<&str> = self.nodes.iter().map(String::as_str).collect();
+
+        let mut reached = BTreeMap::new();
+        let mut pending: VecDeque<(&str, u8)> =
+            start_instances.iter().map(|s| (s.as_str(), 0)).collect();
+        while let Some((domain, distance)) = pending.pop_front() {
+            // Instances which are not in the graph failed to crawl, or were never reached
+            if reached.contains_key(domain) || !nodes.contains(domain) {
+                continue;
+            }
+            reached.insert(domain.to_string(), distance);
+            if distance < max_distance {
+                let next = linked.get(domain).into_iter().flatten();
+                pending.extend(
+                    next.filter(|d| domain_policy.allows(d))
+                        .map(|d| (*d, distance + 1)),
+                );
+            }
+        }
+        let missed = self
+            .nodes
+            .iter()
+            .filter(|n| !reached.contains_key(*n))
+            .cloned()
+            .collect();
+        Simulation { reached, missed }
+    }
+
+    /// Split the graph into connected components, where two instances are connected if either
+    /// links or allows the other and neither blocks the other. Instances outside the largest
+    /// component are reported as islands.
+    pub fn analyze(&self) -> GraphAnalysis {
+        let index: HashMap<&str, usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.as_str(), i))
+            .collect();
+        let blocked: HashSet<(usize, usize)> = self
+            .edges
+            .iter()
+            .filter(|e| e.kind == EdgeKind::Blocked)
+            .map(|e| (index[e.source.as_str()], index[e.target.as_str()]))
+            .flat_map(|(s, t)| [(s, t), (t, s)])
+            .collect();
+        let mut parents: Vec<usize> = (0..self.nodes.len()).collect();
+        for e in self.edges.iter().filter(|e| e.kind != EdgeKind::Blocked) {
+            let (s, t) = (index[e.source.as_str()], index[e.target.as_str()]);
+            if !blocked.contains(&(s, t)) {
+                let (s, t) = (find_root(&mut parents, s), find_root(&mut parents, t));
+                parents[s] = t;
+            }
+        }
+        let mut components: HashMap<usize, Vec<&str>> = HashMap::new();
+        for (i, n) in self.nodes.iter().enumerate() {
+            let root = find_root(&mut parents, i);
+            components.entry(root).or_default().push(n);
+        }
+        let mut components: Vec<_> = components.into_values().collect();
+        components.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        let islands = components
+            .iter()
+            .skip(1)
+            .flat_map(|c| {
+                let component_size = c.len();
+                c.iter().map(move |domain| Island {
+                    domain: domain.to_string(),
+                    component_size,
+                })
+            })
+            .collect();
+        GraphAnalysis {
+            component_sizes: components.iter().map(Vec::len).collect(),
+            islands,
+        }
+    }
+
+    /// Degree and PageRank of each instance over linked edges
+    pub fn centrality(&self) -> HashMap<String, Centrality> {
+        let n = self.nodes.len();
+        let index: HashMap<&str, usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.as_str(), i))
+            .collect();
+        let mut outgoing: Vec<Vec<usize>> = vec![vec![]; n];
+        let mut in_degree = vec![0; n];
+        for e in self.edges.iter().filter(|e| e.kind == EdgeKind::Linked) {
+            let (s, t) = (index[e.source.as_str()], index[e.target.as_str()]);
+            outgoing[s].push(t);
+            in_degree[t] += 1;
+        }
+
+        let mut ranks = vec![1.0 / n as f64; n];
+        for _ in 0..PAGERANK_ITERATIONS {
+            // Rank of instances without outgoing links is spread over all instances
+            let dangling: f64 = (0..n)
+                .filter(|i| outgoing[*i].is_empty())
+                .map(|i| ranks[i])
+                .sum();
+            let base = (1.0 - PAGERANK_DAMPING + PAGERANK_DAMPING * dangling) / n as f64;
+            let mut next = vec![base; n];
+            for (s, targets) in outgoing.iter().enumerate() {
+                for t in targets {
+                    next[*t] += PAGERANK_DAMPING * ranks[s] / targets.len() as f64;
+                }
+            }
+            let change: f64 = next.iter().zip(&ranks).map(|(a, b)| (a - b).abs()).sum();
+            ranks = next;
+            if change < PAGERANK_TOLERANCE {
+                break;
+            }
+        }
+
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, domain)| {
+                let centrality = Centrality {
+                    in_degree: in_degree[i],
+                    out_degree: outgoing[i].len(),
+                    pagerank: ranks[i],
+                };
+                (domain.clone(), centrality)
+            })
+            .collect()
+    }
+
+    /// Graphviz DOT format
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph federation {\n");
+        // writing to a string can't fail
+        for n in &self.nodes {
+            let _ = writeln!(dot, "  {n:?};");
+        }
+        for e in &self.edges {
+            let _ = writeln!(
+                dot,
+                "  {:?} -> {:?} [label={:?}, color={:?}];",
+                e.source,
+                e.target,
+                e.kind.name(),
+                e.kind.color()
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// GEXF format, as used by Gephi
+    pub fn to_gexf(&self) -> String {
+        let mut gexf = String::from(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<gexf xmlns="http://gexf.net/1.3" version="1.3">
+  <graph defaultedgetype="directed">
+    <attributes class="edge">
+      <attribute id="kind" title="kind" type="string"/>
+    </attributes>
+    <nodes>
+"#,
+        );
+        for n in &self.nodes {
+            let n = xml_escape(n);
+            let _ = writeln!(gexf, r#"      <node id="{n}" label="{n}"/>"#);
+        }
+        gexf.push_str("    </nodes>\n    <edges>\n");
+        for (i, e) in self.edges.iter().enumerate() {
+            let _ = writeln!(
+                gexf,
+                r#"      <edge id="{i}" source="{}" target="{}"><attvalues><attvalue for="kind" value="{}"/></attvalues></edge>"#,
+                xml_escape(&e.source),
+                xml_escape(&e.target),
+                e.kind.name()
+            );
+        }
+        gexf.push_str("    </edges>\n  </graph>\n</gexf>\n");
+        gexf
+    }
+
+    /// Nonzero entries of the adjacency matrix, sorted by row and column. Indexes are positions
+    /// in `nodes`, values are the combined flags of all edge kinds between two instances.
+    fn adjacency(&self) -> BTreeMap<(usize, usize), u8> {
+        let index: HashMap<&str, usize> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.as_str(), i))
+            .collect();
+        let mut entries = BTreeMap::new();
+        for e in &self.edges {
+            if let (Some(s), Some(t)) = (index.get(e.source.as_str()), index.get(e.target.as_str()))
+            {
+                *entries.entry((*s, *t)).or_default() |= e.kind.flag();
+            }
+        }
+        entries
+    }
+
+    /// Adjacency matrix in compressed sparse row format
+    pub fn to_csr(&self) -> SparseAdjacency {
+        let entries = self.adjacency();
+        let mut row_lengths = vec![0; self.nodes.len()];
+        for (row, _) in entries.keys() {
+            row_lengths[*row] += 1;
+        }
+        let indptr = iter::once(0)
+            .chain(row_lengths.iter().scan(0, |end, len| {
+                *end += len;
+                Some(*end)
+            }))
+            .collect();
+        SparseAdjacency {
+            nodes: self.nodes.clone(),
+            indptr,
+            indices: entries.keys().map(|(_, column)| *column).collect(),
+            values: entries.into_values().collect(),
+        }
+    }
+
+    /// Matrix Market coordinate format, with the instance index table in comments. Indexes
+    /// start at 1, as the format requires.
+    pub fn to_matrix_market(&self) -> String {
+        let entries = self.adjacency();
+        let mut mtx = String::from("%%MatrixMarket matrix coordinate integer general\n");
+        mtx.push_str(
+            "% Federation between Lemmy instances, rows are sources and columns targets\n",
+        );
+        mtx.push_str("% Values are bit flags: 1 linked, 2 allowed, 4 blocked\n");
+        for (i, n) in self.nodes.iter().enumerate() {
+            let _ = writeln!(mtx, "% {} {n}", i + 1);
+        }
+        let _ = writeln!(mtx, "{0} {0} {1}", self.nodes.len(), entries.len());
+        for ((row, column), value) in entries {
+            let _ = writeln!(mtx, "{} {} {value}", row + 1, column + 1);
+        }
+        mtx
+    }
+
+    /// Write as GEXF if the file extension is `.gexf`, as Matrix Market for `.mtx`, as
+    /// compressed sparse row JSON for `.json`, otherwise as DOT
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let content = match path.extension().and_then(|e| e.to_str()) {
+            Some("gexf") => self.to_gexf(),
+            Some("mtx") => self.to_matrix_market(),
+            Some("json") => serde_json::to_string(&self.to_csr())?,
+            _ => self.to_dot(),
+        };
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+/// Adjacency matrix in compressed sparse row format. The targets of row `i` are
+/// `indices[indptr[i]..indptr[i + 1]]`, with the edge kinds at the same positions in `values`.
+#[derive(Debug, Serialize)]
+pub struct SparseAdjacency {
+    /// Instance domains, the position in this list is the row and column index
+    pub nodes: Vec<String>,
+    pub indptr: Vec<usize>,
+    pub indices: Vec<usize>,
+    /// Bit flags: 1 linked, 2 allowed, 4 blocked
+    pub values: Vec<u8>,
+}
+
+/// Result of a simulated crawl
+#[derive(Debug, Serialize)]
+pub struct Simulation {
+    /// Domains of reached instances, with their crawl distance
+    pub reached: BTreeMap<String, u8>,
+    /// Instances in the graph which would not be reached
+    pub missed: Vec<String>,
+}
+
+/// Connected components of the federation graph
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct GraphAnalysis {
+    /// Number of instances in each component, largest first. The first one is the main
+    /// component.
+    pub component_sizes: Vec<usize>,
+    /// Instances which can't be reached from the main component, by domain
+    pub islands: Vec<Island>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Island {
+    pub domain: String,
+    /// Number of instances in the same component, 1 if the instance is fully isolated
+    pub component_size: usize,
+}
+
+/// Root of the union-find tree which contains `i`, compressing the path on the way
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Position of an instance in the federation graph, from linked edges between crawled instances
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Centrality {
+    /// Crawled instances which link this instance
+    pub in_degree: usize,
+    /// Crawled instances which this instance links
+    pub out_degree: usize,
+    /// Ranks of all instances sum up to 1
+    pub pagerank: f64,
+}
+
+/// Set the centrality of each crawl result, from the links between all of them
+pub fn add_centrality(results: &mut [CrawlResult]) {
+    let mut centrality = FederationGraph::new(results).centrality();
+    for r in results.iter_mut() {
+        r.centrality = centrality.remove(&r.domain);
+    }
+}
+
+/// Compare how many instances each instance reports as linked, with how many crawled instances
+/// report it as linked. Large differences indicate stale federation data.
+pub fn count_links(results: &mut [CrawlResult]) {
+    let mut linked_by: HashMap<String, usize> = HashMap::new();
+    for r in results.iter_mut() {
+        let linked = r.federated_instances.as_ref().map(|f| f.linked());
+        r.linked_count = linked.as_ref().map(Vec::len);
+        for domain in linked.into_iter().flatten() {
+            *linked_by.entry(domain).or_default() += 1;
+        }
+    }
+    for r in results.iter_mut() {
+        r.linked_by_count = linked_by.get(&r.domain).copied().unwrap_or_default();
+    }
+}
+
+pub(crate) fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(nodes: &[&str], edges: &[(&str, &str, EdgeKind)]) -> FederationGraph {
+        FederationGraph {
+            nodes: nodes.iter().map(|n| n.to_string()).collect(),
+            edges: edges
+                .iter()
+                .map(|(source, target, kind)| Edge {
+                    source: source.to_string(),
+                    target: target.to_string(),
+                    kind: *kind,
+                })
+                .collect(),
+        }
+    }
+
+    /// a links and blocks b, allows c, and c links a. d has no edges.
+    fn sparse_graph() -> FederationGraph {
+        graph(
+            &["a", "b", "c", "d"],
+            &[
+                ("a", "b", EdgeKind::Linked),
+                ("a", "b", EdgeKind::Blocked),
+                ("a", "c", EdgeKind::Allowed),
+                ("c", "a", EdgeKind::Linked),
+            ],
+        )
+    }
+
+    #[test]
+    fn csr_has_rows_of_targets_with_combined_flags() {
+        let csr = sparse_graph().to_csr();
+        assert_eq!(csr.nodes, ["a", "b", "c", "d"]);
+        assert_eq!(csr.indptr, [0, 2, 2, 3, 3]);
+        assert_eq!(csr.indices, [1, 2, 0]);
+        assert_eq!(csr.values, [5, 2, 1]);
+    }
+
+    #[test]
+    fn matrix_market_starts_indexes_at_one() {
+        let mtx = sparse_graph().to_matrix_market();
+        let lines: Vec<_> = mtx.lines().filter(|l| !l.starts_with('%')).collect();
+        assert_eq!(lines, ["4 4 3", "1 2 5", "1 3 2", "3 1 1"]);
+        assert!(mtx.starts_with("%%MatrixMarket matrix coordinate integer general\n"));
+        assert!(mtx.contains("% 1 a\n% 2 b\n% 3 c\n% 4 d\n"));
+    }
+
+    #[test]
+    fn components_ignore_blocked_links() {
+        let analysis = graph(
+            &["a", "b", "c", "d", "e", "f", "g", "h"],
+            &[
+                ("a", "b", EdgeKind::Linked),
+                ("c", "b", EdgeKind::Allowed),
+                ("d", "e", EdgeKind::Linked),
+                ("e", "d", EdgeKind::Blocked),
+                ("g", "h", EdgeKind::Linked),
+            ],
+        )
+        .analyze();
+        assert_eq!(analysis.component_sizes, [3, 2, 1, 1, 1]);
+        let islands: Vec<_> = analysis
+            .islands
+            .iter()
+            .map(|i| (i.domain.as_str(), i.component_size))
+            .collect();
+        assert_eq!(islands, [("g", 2), ("h", 2), ("d", 1), ("e", 1), ("f", 1)]);
+    }
+
+    #[test]
+    fn block_in_either_direction_splits_a_component() {
+        let analysis = sparse_graph().analyze();
+        assert_eq!(analysis.component_sizes, [2, 1, 1]);
+        let islands: Vec<_> = analysis.islands.iter().map(|i| i.domain.as_str()).collect();
+        assert_eq!(islands, ["b", "d"]);
+        let analysis = graph(&["a", "b"], &[("a", "b", EdgeKind::Linked)]).analyze();
+        assert_eq!(analysis.component_sizes, [2]);
+        assert!(analysis.islands.is_empty());
+    }
+
+    #[test]
+    fn pagerank_sums_to_one_with_dangling_instances() {
+        let centrality = graph(
+            &["a", "b", "c", "d"],
+            &[
+                ("a", "b", EdgeKind::Linked),
+                ("a", "c", EdgeKind::Linked),
+                ("b", "c", EdgeKind::Linked),
+                ("c", "a", EdgeKind::Blocked),
+            ],
+        )
+        .centrality();
+        let sum: f64 = centrality.values().map(|c| c.pagerank).sum();
+        assert!((sum - 1.0).abs() < 1e-6, "pagerank sums to {}", sum);
+        assert!(centrality["c"].pagerank > centrality["b"].pagerank);
+        assert!(centrality["b"].pagerank > centrality["a"].pagerank);
+        assert!((centrality["a"].pagerank - centrality["d"].pagerank).abs() < 1e-9);
+        assert_eq!(centrality["a"].out_degree, 2);
+        assert_eq!(centrality["c"].in_degree, 2);
+        // Only linked edges count
+        assert_eq!(centrality["c"].out_degree, 0);
+        assert_eq!(centrality["a"].in_degree, 0);
+    }
+}
@@ -1,109 +1,1013 @@
 #[macro_use]
 extern crate derive_new;
 
+use aggregate::RunningTotals;
 use anyhow::Error;
-use crawl::CrawlParams;
-use crawl::{CrawlJob, CrawlResult};
-use log::{debug, trace};
+use checkpoint::{Checkpoint, CHECKPOINT_INTERVAL};
+use connect::Connector;
+use crawl::{normalize_domain, CrawlParams};
+use crawl::{
+    CrawlFailure, CrawlJob, CrawlOutcome, CrawlResult, CrawlWarning, DiscoveryReport,
+    MbinCrawlResult, OptedOutInstance, OtherSoftwareInstance, PrivateApiInstance,
+};
+use derate::ConcurrencyLimiter;
+use dns::{CachingResolver, DnsServer};
+use filter::{InstanceFilter, SharedFilter};
+use graph::{add_centrality, count_links};
+use memory::{resident_memory, SpillBuffer, MEMORY_CHECK_INTERVAL};
+use once_cell::sync::OnceCell;
+use optout::{OptOutMode, CRAWLER_USER_AGENT};
+use paths::{PathOverride, PathOverrides};
+use policy::DomainPolicy;
+use progress::{CrawlProgress, PROGRESS_INTERVAL};
+use queue::JobQueue;
+use ratelimit::{RateLimitMiddleware, RateLimiter};
 use reqwest::redirect::Policy;
+use reqwest::Proxy;
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
+use retry::{BudgetedRetryStrategy, RetryBudget};
 use semver::Version;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::future::{pending, Future};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc::{UnboundedReceiver, WeakUnboundedSender};
 use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{interval_at, Instant};
+use tracing::{debug, info_span, trace, warn, Instrument};
+use ulid::Ulid;
 
+pub mod aggregate;
+mod checkpoint;
+mod connect;
+pub mod contact;
 pub mod crawl;
+mod derate;
+pub mod diagnostics;
+pub mod diff;
+pub mod directory;
+pub mod dns;
+pub mod filter;
+pub mod grafana;
+pub mod graph;
+mod memory;
+pub mod metrics;
+pub mod migrate;
+pub mod notify;
+pub mod optout;
+pub mod output;
+pub mod pages;
+pub mod paths;
+pub mod policy;
+pub mod probe;
+pub mod progress;
+pub mod query;
+mod queue;
+mod ratelimit;
+pub mod recommend;
+pub mod redact;
+mod retry;
+pub mod rollup;
+pub mod s3;
+pub mod server;
+pub mod service;
+pub mod size;
+pub mod storage;
 mod structs;
+pub mod tls;
+pub mod topics;
+pub mod webhook;
 
-fn build_client(timeout: Duration) -> ClientWithMiddleware {
-    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
-    let client = reqwest::ClientBuilder::new()
+fn client_builder(
+    timeout: Duration,
+    user_agent: &str,
+    proxy: Option<Proxy>,
+    resolver: Option<CachingResolver>,
+) -> reqwest::ClientBuilder {
+    let mut client = reqwest::ClientBuilder::new();
+    if let Some(proxy) = proxy {
+        client = client.proxy(proxy);
+    }
+    if let Some(resolver) = resolver {
+        client = client.dns_resolver(Arc::new(resolver));
+    }
+    client
         .timeout(timeout)
         .connect_timeout(timeout)
-        .user_agent("lemmy-stats-crawler")
+        .user_agent(user_agent)
         .pool_idle_timeout(Some(Duration::from_millis(100)))
         .pool_max_idle_per_host(1)
         .redirect(Policy::none())
-        .build()
-        .expect("build reqwest client");
-    ClientBuilder::new(client)
-        .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-        .build()
 }
 
-pub async fn start_crawl(
+/// Retries and rate limit for a single run. The retry budget is separate for each run, also when
+/// runs share the client.
+fn with_middleware(
+    client: reqwest::Client,
+    retry_budget: Arc<RetryBudget>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+) -> ClientWithMiddleware {
+    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
+    let mut builder =
+        ClientBuilder::new(client).with(RetryTransientMiddleware::new_with_policy_and_strategy(
+            retry_policy,
+            BudgetedRetryStrategy(retry_budget),
+        ));
+    // after the retry middleware, so that retries are throttled as well
+    if let Some(rate_limiter) = rate_limiter {
+        builder = builder.with(RateLimitMiddleware(rate_limiter));
+    }
+    builder.build()
+}
+
+/// HTTP client of a crawler without its middleware, and the connector for other connections to
+/// instances which shares its resolver and rate limit
+#[derive(Debug, Clone)]
+struct Clients {
+    http: reqwest::Client,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Missing with a proxy
+    connector: Option<Connector>,
+    /// Only sends HTTP/3, missing unless enabled and without a proxy
+    #[cfg(feature = "http3")]
+    http3: Option<reqwest::Client>,
+}
+
+/// User agent with the crawler name and version, which instances can match in robots.txt. Custom
+/// details like contact info are put in front.
+fn user_agent(custom: Option<&str>) -> String {
+    let crawler = format!("{CRAWLER_USER_AGENT}/{}", env!("CARGO_PKG_VERSION"));
+    match custom {
+        Some(custom) => format!("{custom} {crawler}"),
+        None => crawler,
+    }
+}
+
+/// Instances which were crawled successfully, and those which failed
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CrawlOutput {
+    /// ULID which identifies the crawl, kept when a crawl is resumed from a checkpoint
+    #[serde(default)]
+    pub run_id: String,
+    pub results: Vec<CrawlResult>,
+    pub failures: Vec<CrawlFailure>,
+    /// Non-Lemmy instances, only with `count_other_software`
+    pub other_software: Vec<OtherSoftwareInstance>,
+    /// Only with `include_mbin`
+    pub mbin_results: Vec<MbinCrawlResult>,
+    /// Instances which require login to access the API
+    pub private_api: Vec<PrivateApiInstance>,
+    /// Instances which opted out in robots.txt, empty with `OptOutMode::Skip`
+    #[serde(default)]
+    pub opted_out: Vec<OptedOutInstance>,
+    #[serde(default)]
+    pub warnings: Vec<CrawlWarning>,
+    #[serde(default)]
+    pub discovery: DiscoveryReport,
+    /// Updated as results are received, see `aggregate()`
+    #[serde(default)]
+    pub(crate) totals: RunningTotals,
+    /// The crawl was stopped early, so some instances are missing
+    pub partial: bool,
+}
+
+impl CrawlOutput {
+    fn add(&mut self, outcome: CrawlOutcome) {
+        match outcome {
+            CrawlOutcome::Success(res) => self.results.push(*res),
+            CrawlOutcome::Failure(failure) => self.failures.push(failure),
+            CrawlOutcome::OtherSoftware(other) => self.other_software.push(other),
+            CrawlOutcome::Mbin(res) => self.mbin_results.push(*res),
+            CrawlOutcome::PrivateApi(res) => self.private_api.push(*res),
+            CrawlOutcome::OptedOut(res) => self.opted_out.push(res),
+            CrawlOutcome::Warning(warning) => self.warnings.push(warning),
+            CrawlOutcome::Discovery(report) => self.discovery.merge(report),
+        }
+    }
+
+    /// Fill in the fields which depend on all results, and sort them. Needs to be called again
+    /// when results are added later.
+    pub(crate) fn finish(&mut self, graph_metrics: bool) {
+        count_links(&mut self.results);
+        if graph_metrics {
+            add_centrality(&mut self.results);
+        }
+
+        // Sort by active monthly users descending
+        self.results
+            .sort_unstable_by_key(|i| i.site_info.users_active_month());
+        self.results.reverse();
+        self.failures
+            .sort_unstable_by(|a, b| a.domain.cmp(&b.domain));
+        self.other_software
+            .sort_unstable_by(|a, b| a.domain.cmp(&b.domain));
+        self.mbin_results
+            .sort_unstable_by_key(|i| i.node_info.usage.users.active_month);
+        self.mbin_results.reverse();
+        self.private_api
+            .sort_unstable_by(|a, b| a.domain.cmp(&b.domain));
+        self.opted_out
+            .sort_unstable_by(|a, b| a.domain.cmp(&b.domain));
+        self.warnings.sort_by(|a, b| a.domain.cmp(&b.domain));
+    }
+}
+
+/// Configures and runs a crawl of the Lemmy network, eg
+/// `Crawler::new().jobs(100).max_distance(10).run().await`
+#[derive(Debug, Clone)]
+pub struct Crawler {
     start_instances: Vec<String>,
+    seed_weights: HashMap<String, u32>,
     exclude_domains: Vec<String>,
+    domain_policy: DomainPolicy,
     jobs_count: u32,
     max_distance: u8,
     timeout: Duration,
-) -> Result<Vec<CrawlResult>, Error> {
-    let (crawl_jobs_sender, crawl_jobs_receiver) = mpsc::unbounded_channel::<CrawlJob>();
-    let (results_sender, mut results_receiver) = mpsc::unbounded_channel();
-    let client = build_client(timeout);
-    let params = Arc::new(CrawlParams::new(
-        min_lemmy_version(&client).await?,
-        exclude_domains.into_iter().collect(),
-        max_distance,
-        Mutex::new(HashSet::new()),
-        results_sender,
-        client,
-    ));
-
-    let rcv = Arc::new(Mutex::new(crawl_jobs_receiver));
-    let send = crawl_jobs_sender.downgrade();
-    for i in 0..jobs_count {
-        let rcv = rcv.clone();
-        let send = send.clone();
-        tokio::spawn(background_task(i, send, rcv));
-    }
-
-    for domain in start_instances.into_iter() {
-        let job = CrawlJob::new(domain, 0, params.clone());
-        crawl_jobs_sender.send(job).unwrap();
-    }
-
-    // give time to start background tasks
-    tokio::time::sleep(Duration::from_secs(1)).await;
-    drop(params);
-
-    let mut results = vec![];
-    while let Some(res) = results_receiver.recv().await {
-        results.push(res);
-    }
-
-    // Sort by active monthly users descending
-    results.sort_unstable_by_key(|i| i.site_info.users_active_month());
-    results.reverse();
-    Ok(results)
-}
-
-async fn background_task(
-    i: u32,
-    sender: WeakUnboundedSender<CrawlJob>,
-    rcv: Arc<Mutex<UnboundedReceiver<CrawlJob>>>,
-) {
-    loop {
-        let maybe_job = {
-            let mut lock = rcv.lock().await;
-            lock.recv().await
+    min_version: MinVersion,
+    fork_min_versions: HashMap<String, Version>,
+    adaptive_concurrency: bool,
+    diagnostics: bool,
+    count_other_software: bool,
+    include_mbin: bool,
+    crawl_communities: Option<u32>,
+    deep_instances: Option<HashSet<String>>,
+    deep_crawl_budget: Option<Duration>,
+    known_instances: HashSet<String>,
+    checkpoint: Option<PathBuf>,
+    max_memory: Option<u64>,
+    max_duration: Option<Duration>,
+    queue_capacity: Option<usize>,
+    global_rps: Option<f64>,
+    user_agent: Option<String>,
+    proxy: Option<Proxy>,
+    dns_cache: bool,
+    dns_server: Option<DnsServer>,
+    opt_out_mode: OptOutMode,
+    slow_retry_multiplier: Option<f64>,
+    progress: Option<mpsc::UnboundedSender<CrawlProgress>>,
+    instance_filter: Option<SharedFilter>,
+    path_overrides: PathOverrides,
+    dns_prefilter: bool,
+    include_onion: bool,
+    ignore_robots: bool,
+    collect_tls: bool,
+    #[cfg(feature = "http3")]
+    http3: bool,
+    classify_topics: bool,
+    graph_metrics: bool,
+    retry_budget: f64,
+    /// Built on first run, and reused by later runs of this crawler and its clones, unless an
+    /// option which affects the clients is changed
+    clients: Arc<OnceCell<Clients>>,
+}
+
+impl Default for Crawler {
+    fn default() -> Self {
+        Crawler {
+            start_instances: vec!["lemmy.ml".to_string()],
+            seed_weights: HashMap::new(),
+            exclude_domains: vec![],
+            domain_policy: DomainPolicy::default(),
+            jobs_count: 100,
+            max_distance: 10,
+            timeout: Duration::from_secs(10),
+            min_version: MinVersion::Auto,
+            fork_min_versions: HashMap::new(),
+            adaptive_concurrency: true,
+            diagnostics: false,
+            count_other_software: false,
+            include_mbin: false,
+            crawl_communities: None,
+            deep_instances: None,
+            deep_crawl_budget: None,
+            known_instances: HashSet::new(),
+            checkpoint: None,
+            max_memory: None,
+            max_duration: None,
+            queue_capacity: Some(10_000),
+            global_rps: None,
+            user_agent: None,
+            proxy: None,
+            dns_cache: false,
+            dns_server: None,
+            opt_out_mode: OptOutMode::Counts,
+            slow_retry_multiplier: None,
+            progress: None,
+            instance_filter: None,
+            path_overrides: PathOverrides::default(),
+            dns_prefilter: false,
+            include_onion: false,
+            ignore_robots: false,
+            collect_tls: false,
+            #[cfg(feature = "http3")]
+            http3: false,
+            classify_topics: false,
+            graph_metrics: false,
+            retry_budget: 0.2,
+            clients: Default::default(),
+        }
+    }
+}
+
+impl Crawler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lemmy instance domains where the crawl should be started
+    pub fn start_instances(mut self, start_instances: Vec<String>) -> Self {
+        self.start_instances = start_instances;
+        self
+    }
+
+    /// Crawl exactly these instances, without following any federation links
+    pub fn only_instances(self, domains: Vec<String>) -> Self {
+        self.start_instances(domains).max_distance(0)
+    }
+
+    /// Weights for start instances. Instances discovered from a seed with higher weight are
+    /// crawled first. Seeds without weight default to 0.
+    pub fn seed_weights(mut self, seed_weights: HashMap<String, u32>) -> Self {
+        self.seed_weights = seed_weights;
+        self
+    }
+
+    /// Lemmy instance domains which should not be crawled. Takes precedence over the domain
+    /// policy.
+    pub fn exclude_domains(mut self, exclude_domains: Vec<String>) -> Self {
+        self.exclude_domains = exclude_domains;
+        self
+    }
+
+    /// Rules for which discovered domains are crawled
+    pub fn domain_policy(mut self, domain_policy: DomainPolicy) -> Self {
+        self.domain_policy = domain_policy;
+        self
+    }
+
+    /// Number of crawl jobs to run in parallel
+    pub fn jobs(mut self, jobs_count: u32) -> Self {
+        self.jobs_count = jobs_count;
+        self
+    }
+
+    /// Temporarily crawl fewer instances at the same time, while network errors spike across
+    /// many instances. Enabled by default.
+    pub fn adaptive_concurrency(mut self, adaptive_concurrency: bool) -> Self {
+        self.adaptive_concurrency = adaptive_concurrency;
+        self
+    }
+
+    /// Maximum crawl distance from start instances
+    pub fn max_distance(mut self, max_distance: u8) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+
+    /// Timeout for HTTP requests
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.clients = Default::default();
+        self
+    }
+
+    /// Minimum Lemmy version of crawled instances, older ones are counted as failed. Doesn't
+    /// apply to forks, see `fork_min_versions`.
+    pub fn min_version(mut self, min_version: MinVersion) -> Self {
+        self.min_version = min_version;
+        self
+    }
+
+    /// Minimum versions of Lemmy forks and other software with Lemmy API, keyed by the software
+    /// name from nodeinfo and compared with the nodeinfo version. Forks without entry are
+    /// crawled on all versions.
+    pub fn fork_min_versions(mut self, fork_min_versions: HashMap<String, Version>) -> Self {
+        self.fork_min_versions = fork_min_versions;
+        self
+    }
+
+    /// Include DNS and per-endpoint request timings in the results
+    pub fn diagnostics(mut self, diagnostics: bool) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Record name, version and user counts of instances running other fediverse software,
+    /// instead of treating them as failed
+    pub fn count_other_software(mut self, count_other_software: bool) -> Self {
+        self.count_other_software = count_other_software;
+        self
+    }
+
+    /// Crawl Mbin and Kbin instances which are found in federation lists, instead of treating
+    /// them as failed. Their results are returned separately from Lemmy instances.
+    pub fn include_mbin(mut self, include_mbin: bool) -> Self {
+        self.include_mbin = include_mbin;
+        self
+    }
+
+    /// Fetch local communities of each instance, up to this many pages of 50. `None` disables
+    /// community crawling.
+    pub fn crawl_communities(mut self, max_pages: Option<u32>) -> Self {
+        self.crawl_communities = max_pages;
+        self
+    }
+
+    /// Only fetch communities of these instances, eg the largest ones of the previous crawl, and
+    /// crawl all others without communities. `None` fetches communities of all instances.
+    pub fn deep_instances(mut self, deep_instances: Option<HashSet<String>>) -> Self {
+        self.deep_instances = deep_instances;
+        self
+    }
+
+    /// Stop fetching communities once the crawl has run this long, so that the remaining
+    /// instances are crawled with site info only and the crawl finishes on schedule
+    pub fn deep_crawl_budget(mut self, deep_crawl_budget: Option<Duration>) -> Self {
+        self.deep_crawl_budget = deep_crawl_budget;
+        self
+    }
+
+    /// Domains which were crawled successfully before. If one of them now serves a parking page
+    /// or other software, it is reported as repurposed instead of failed.
+    pub fn known_instances(mut self, known_instances: HashSet<String>) -> Self {
+        self.known_instances = known_instances;
+        self
+    }
+
+    /// File where the state of the crawl is saved every minute. If the file exists when the
+    /// crawl starts, it is resumed from there. Removed once the crawl is complete.
+    pub fn checkpoint(mut self, checkpoint: Option<PathBuf>) -> Self {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    /// Once the process uses more memory than this many bytes, communities and diagnostics are
+    /// dropped from results, and results are buffered on disk until the crawl is complete. Only
+    /// supported on Linux.
+    pub fn max_memory(mut self, max_memory: Option<u64>) -> Self {
+        self.max_memory = max_memory;
+        self
+    }
+
+    /// Stop the crawl after this time, and return the results collected until then with
+    /// `partial` set
+    pub fn max_duration(mut self, max_duration: Option<Duration>) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
+
+    /// Maximum number of queued jobs. Once reached, jobs which discover new instances wait until
+    /// there is space in the queue. `None` for an unbounded queue.
+    pub fn queue_capacity(mut self, queue_capacity: Option<usize>) -> Self {
+        self.queue_capacity = queue_capacity;
+        self
+    }
+
+    /// Maximum number of HTTP requests per second across all crawl jobs
+    pub fn global_rps(mut self, global_rps: Option<f64>) -> Self {
+        self.global_rps = global_rps;
+        self.clients = Default::default();
+        self
+    }
+
+    /// Sent in front of the crawler name and version in the user agent, eg to include contact
+    /// info for instance admins
+    pub fn user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self.clients = Default::default();
+        self
+    }
+
+    /// Send all requests through this HTTP or SOCKS5 proxy. Use `socks5h://` to resolve domains
+    /// through the proxy as well.
+    pub fn proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.proxy = proxy;
+        self.clients = Default::default();
+        self
+    }
+
+    /// Resolve domains with a caching resolver, instead of asking the system for each request
+    pub fn dns_cache(mut self, dns_cache: bool) -> Self {
+        self.dns_cache = dns_cache;
+        self.clients = Default::default();
+        self
+    }
+
+    /// Resolve domains with this server and cache the results, instead of using the system
+    /// configuration
+    pub fn dns_server(mut self, dns_server: Option<DnsServer>) -> Self {
+        self.dns_server = dns_server;
+        self.clients = Default::default();
+        self
+    }
+
+    /// What to record for instances which disallow `lemmy-stats-crawler` from any API path in
+    /// their robots.txt
+    pub fn opt_out_mode(mut self, opt_out_mode: OptOutMode) -> Self {
+        self.opt_out_mode = opt_out_mode;
+        self
+    }
+
+    /// Crawl instances which time out once more, with the timeout multiplied by this factor.
+    /// Instances which only answer then are marked with `slow_response`.
+    pub fn slow_retry_multiplier(mut self, multiplier: Option<f64>) -> Self {
+        self.slow_retry_multiplier = multiplier;
+        self
+    }
+
+    /// Send the progress of the crawl to this channel, every second while crawling
+    pub fn progress(mut self, sender: Option<mpsc::UnboundedSender<CrawlProgress>>) -> Self {
+        self.progress = sender;
+        self
+    }
+
+    /// Only include instances which the filter accepts, and only crawl links of those. Unlike
+    /// filtering the output, this also avoids crawling parts of the network which are only
+    /// reachable through rejected instances.
+    pub fn instance_filter(mut self, filter: impl InstanceFilter + 'static) -> Self {
+        self.instance_filter = Some(SharedFilter::new(filter));
+        self
+    }
+
+    /// Request endpoints at nonstandard paths, for instances behind reverse proxies with a
+    /// different layout
+    pub fn path_overrides(mut self, path_overrides: Vec<PathOverride>) -> Self {
+        self.path_overrides = PathOverrides::new(path_overrides);
+        self
+    }
+
+    /// Resolve newly discovered domains in bulk before queueing them, and record those which
+    /// don't exist as failed without sending HTTP requests
+    pub fn dns_prefilter(mut self, dns_prefilter: bool) -> Self {
+        self.dns_prefilter = dns_prefilter;
+        self
+    }
+
+    /// Crawl `.onion` domains over plain HTTP and mark them with `onion`. Requires a proxy to
+    /// Tor which resolves domains itself, eg `socks5h://127.0.0.1:9050`.
+    pub fn include_onion(mut self, include_onion: bool) -> Self {
+        self.include_onion = include_onion;
+        self
+    }
+
+    /// Don't fetch robots.txt, so that instances which opted out are crawled like all others
+    pub fn ignore_robots(mut self, ignore_robots: bool) -> Self {
+        self.ignore_robots = ignore_robots;
+        self
+    }
+
+    /// Connect to each instance once more to read its TLS certificate, see `CrawlResult::tls`.
+    /// Skipped with a proxy, which these connections would bypass.
+    pub fn collect_tls(mut self, collect_tls: bool) -> Self {
+        self.collect_tls = collect_tls;
+        self
+    }
+
+    /// Request the site of each instance over HTTP/3 first, and fall back to HTTP/2 or 1.1 if
+    /// that fails, see `CrawlResult::http_version`. Each instance without HTTP/3 costs an extra
+    /// request, or a timeout if UDP is blocked. Skipped with a proxy, which HTTP/3 would bypass.
+    #[cfg(feature = "http3")]
+    pub fn http3(mut self, http3: bool) -> Self {
+        self.http3 = http3;
+        self.clients = Default::default();
+        self
+    }
+
+    /// Tag each instance with topics from keyword rules, see `topics::classify`
+    pub fn classify_topics(mut self, classify_topics: bool) -> Self {
+        self.classify_topics = classify_topics;
+        self
+    }
+
+    /// Compute degree and PageRank of each instance after the crawl, see `CrawlResult::centrality`
+    pub fn graph_metrics(mut self, graph_metrics: bool) -> Self {
+        self.graph_metrics = graph_metrics;
+        self
+    }
+
+    /// Maximum number of retries as a fraction of all requests in a crawl, eg 0.2 for 20%. Once
+    /// used up, failed requests are not retried anymore.
+    pub fn retry_budget(mut self, ratio: f64) -> Self {
+        self.retry_budget = ratio;
+        self
+    }
+
+    /// Crawl the network. Can be called repeatedly, eg to recrawl periodically.
+    pub async fn run(&self) -> Result<CrawlOutput, Error> {
+        self.run_streaming(|_| {}).await
+    }
+
+    /// Same as `run`, but additionally passes each crawled instance to `on_result` as soon as
+    /// it is available.
+    pub async fn run_streaming(
+        &self,
+        on_result: impl FnMut(&CrawlResult),
+    ) -> Result<CrawlOutput, Error> {
+        self.run_streaming_until(on_result, pending()).await
+    }
+
+    /// Same as `run_streaming`, but once `stop` completes or `max_duration` has passed, pending
+    /// and in-flight jobs are cancelled and the results collected so far are returned with
+    /// `partial` set.
+    pub async fn run_streaming_until(
+        &self,
+        mut on_result: impl FnMut(&CrawlResult),
+        stop: impl Future<Output = ()>,
+    ) -> Result<CrawlOutput, Error> {
+        let start = Instant::now();
+        let max_duration = self.max_duration;
+        let stop = async move {
+            let deadline = async {
+                match max_duration {
+                    Some(max_duration) => tokio::time::sleep(max_duration).await,
+                    None => pending().await,
+                }
+            };
+            tokio::select! {
+                _ = stop => {}
+                _ = deadline => debug!("Crawl reached maximum duration"),
+            }
+        };
+        let (results_sender, mut results_receiver) = mpsc::unbounded_channel();
+        let Clients {
+            http,
+            rate_limiter,
+            connector,
+            #[cfg(feature = "http3")]
+            http3,
+        } = self
+            .clients
+            .get_or_try_init(|| -> Result<_, Error> {
+                let resolver = (self.dns_cache || self.dns_server.is_some())
+                    .then(|| CachingResolver::new(self.dns_server))
+                    .transpose()?;
+                let rate_limiter = self.global_rps.map(|rps| Arc::new(RateLimiter::new(rps)));
+                let user_agent = user_agent(self.user_agent.as_deref());
+                let http = client_builder(
+                    self.timeout,
+                    &user_agent,
+                    self.proxy.clone(),
+                    resolver.clone(),
+                )
+                .build()?;
+                // HTTP/3 needs its own client, as the TLS config of the other one doesn't offer it
+                // during the handshake
+                #[cfg(feature = "http3")]
+                let http3 = (self.http3 && self.proxy.is_none())
+                    .then(|| {
+                        client_builder(self.timeout, &user_agent, None, resolver.clone())
+                            .http3_prior_knowledge()
+                            .build()
+                    })
+                    .transpose()?;
+                // Connecting directly would bypass the proxy
+                let connector = self
+                    .proxy
+                    .is_none()
+                    .then(|| Connector::new(resolver, rate_limiter.clone(), self.timeout));
+                Ok(Clients {
+                    http,
+                    rate_limiter,
+                    connector,
+                    #[cfg(feature = "http3")]
+                    http3,
+                })
+            })?
+            .clone();
+        // Without retries, as failed attempts fall back to the other HTTP versions
+        #[cfg(feature = "http3")]
+        let http3_client = http3.map(|http3| {
+            let mut builder = ClientBuilder::new(http3);
+            if let Some(rate_limiter) = &rate_limiter {
+                builder = builder.with(RateLimitMiddleware(rate_limiter.clone()));
+            }
+            builder.build()
+        });
+        let retry_budget = Arc::new(RetryBudget::new(self.retry_budget));
+        let client = with_middleware(http, retry_budget, rate_limiter);
+        let min_lemmy_version = match &self.min_version {
+            MinVersion::Auto => Some(min_lemmy_version(&client).await?),
+            MinVersion::Fixed(version) => Some(version.clone()),
+            MinVersion::Any => None,
+        };
+        let resumed = match &self.checkpoint {
+            Some(path) => Checkpoint::load(path)?,
+            None => None,
         };
-        if let Some(job) = maybe_job {
-            let domain = job.domain.clone();
-            debug!(
-                "Worker {i} starting job {domain} at distance {}",
-                job.current_distance
-            );
-            let sender = sender.upgrade().unwrap();
-            let res = job.crawl(sender).await;
-            if let Err(e) = res {
-                trace!("Job {domain} errored with: {}", e)
+        let crawled_instances = match &resumed {
+            Some(checkpoint) => checkpoint.crawled_domains()?,
+            None => HashSet::new(),
+        };
+        let params = Arc::new(CrawlParams {
+            min_lemmy_version,
+            domain_policy: self
+                .domain_policy
+                .clone()
+                .with_excluded(self.exclude_domains.clone()),
+            max_distance: self.max_distance,
+            crawled_instances: Mutex::new(crawled_instances),
+            result_sender: results_sender,
+            client,
+            #[cfg(feature = "http3")]
+            http3_client,
+            diagnostics: self.diagnostics,
+            count_other_software: self.count_other_software,
+            include_mbin: self.include_mbin,
+            crawl_communities: self.crawl_communities,
+            known_instances: self.known_instances.clone(),
+            opt_out_mode: self.opt_out_mode,
+            slow_retry_timeout: self.slow_retry_multiplier.map(|m| self.timeout.mul_f64(m)),
+            instance_filter: self.instance_filter.clone(),
+            path_overrides: self.path_overrides.clone(),
+            dns_prefilter: self.dns_prefilter,
+            include_onion: self.include_onion,
+            ignore_robots: self.ignore_robots,
+            fork_min_versions: self.fork_min_versions.clone(),
+            concurrency: ConcurrencyLimiter::new(
+                self.jobs_count as usize,
+                self.adaptive_concurrency,
+            ),
+            collect_tls: self.collect_tls,
+            classify_topics: self.classify_topics,
+            deep_instances: self.deep_instances.clone(),
+            deep_crawl_deadline: self
+                .deep_crawl_budget
+                .map(|budget| start.into_std() + budget),
+            connector,
+        });
+
+        let run_id = resumed
+            .as_ref()
+            .map(|c| c.output.run_id.clone())
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| Ulid::new().to_string());
+        debug!("Starting crawl {run_id}");
+        let run_span = info_span!("run", %run_id);
+
+        let queue = Arc::new(JobQueue::new(self.queue_capacity, self.jobs_count));
+        let workers = Workers {
+            handles: (0..self.jobs_count)
+                .map(|i| {
+                    tokio::spawn(background_task(i, queue.clone()).instrument(run_span.clone()))
+                })
+                .collect(),
+            queue: queue.clone(),
+        };
+
+        let mut state = match resumed {
+            Some(checkpoint) => {
+                debug!(
+                    "Resuming crawl with {} pending jobs",
+                    checkpoint.pending.len()
+                );
+                // so that streamed output is complete
+                for result in &checkpoint.output.results {
+                    on_result(result);
+                }
+                for job in &checkpoint.pending {
+                    let job = CrawlJob::new(
+                        job.domain.clone(),
+                        job.distance,
+                        job.priority,
+                        params.clone(),
+                    );
+                    queue.push(job);
+                }
+                checkpoint
+            }
+            None => {
+                for domain in &self.start_instances {
+                    let priority = self.seed_weights.get(domain).copied().unwrap_or_default();
+                    let domain = normalize_domain(domain).unwrap_or_else(|| domain.clone());
+                    let job = CrawlJob::new(domain, 0, priority, params.clone());
+                    queue.push(job);
+                }
+                Checkpoint::default()
+            }
+        };
+        state.output.run_id = run_id;
+
+        let mut spill = state.spill.clone().map(SpillBuffer::open).transpose()?;
+
+        // give time to start background tasks
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        drop(params);
+
+        let mut receive = |outcome: CrawlOutcome,
+                           output: &mut CrawlOutput,
+                           spill: &mut Option<SpillBuffer>|
+         -> Result<(), Error> {
+            output.totals.add(&outcome);
+            match (outcome, spill) {
+                (CrawlOutcome::Success(mut res), Some(spill)) => {
+                    on_result(&res);
+                    res.trim();
+                    spill.push(&res)?;
+                }
+                (outcome, _) => {
+                    if let CrawlOutcome::Success(res) = &outcome {
+                        on_result(res);
+                    }
+                    output.add(outcome);
+                }
+            }
+            Ok(())
+        };
+        let mut partial = false;
+        let mut checkpoint_timer =
+            interval_at(Instant::now() + CHECKPOINT_INTERVAL, CHECKPOINT_INTERVAL);
+        let mut memory_timer = interval_at(Instant::now(), MEMORY_CHECK_INTERVAL);
+        let mut progress_timer = interval_at(Instant::now(), PROGRESS_INTERVAL);
+        tokio::pin!(stop);
+        loop {
+            let outcome = tokio::select! {
+                outcome = results_receiver.recv() => outcome,
+                _ = &mut stop, if !partial => {
+                    partial = true;
+                    state.pending = queue.snapshot();
+                    // Dropping the jobs also drops their result senders, so the loop ends once
+                    // already sent results are received
+                    queue.clear();
+                    workers.handles.iter().for_each(|w| w.abort());
+                    continue;
+                }
+                _ = checkpoint_timer.tick(), if self.checkpoint.is_some() && !partial => {
+                    // Jobs which are missing from the snapshot have finished, so their results
+                    // are already in the channel
+                    state.pending = queue.snapshot();
+                    while let Ok(outcome) = results_receiver.try_recv() {
+                        receive(outcome, &mut state.output, &mut spill)?;
+                    }
+                    self.save_checkpoint(&mut state, &mut spill);
+                    continue;
+                }
+                _ = memory_timer.tick(), if self.max_memory.is_some() && spill.is_none() => {
+                    let exceeded = resident_memory()
+                        .zip(self.max_memory)
+                        .map_or(false, |(used, max)| used > max);
+                    if exceeded {
+                        warn!("Memory limit reached, buffering results on disk without \
+                            communities and diagnostics");
+                        let mut buffer = SpillBuffer::open(self.spill_path())?;
+                        for mut res in state.output.results.drain(..) {
+                            res.trim();
+                            buffer.push(&res)?;
+                        }
+                        state.output.results.shrink_to_fit();
+                        spill = Some(buffer);
+                    }
+                    continue;
+                }
+                _ = progress_timer.tick(), if self.progress.is_some() => {
+                    if let Some(sender) = &self.progress {
+                        let (queued_jobs, running_jobs) = queue.counts();
+                        let progress = CrawlProgress {
+                            queued_jobs,
+                            running_jobs,
+                            crawled_instances: state.output.totals.crawled_instances(),
+                            failed_instances: state.output.failures.len(),
+                            elapsed: start.elapsed(),
+                        };
+                        // Only fails if the receiver was dropped, which is fine
+                        sender.send(progress).ok();
+                    }
+                    continue;
+                }
+            };
+            match outcome {
+                Some(outcome) => receive(outcome, &mut state.output, &mut spill)?,
+                None => break,
             }
-        } else {
-            return;
+        }
+        // All jobs are done or dropped, so the workers are only waiting for new jobs
+        workers.join().await;
+
+        // Keep spilled results if they are needed to resume the crawl
+        let keep_spill = partial && self.checkpoint.is_some();
+        if partial {
+            self.save_checkpoint(&mut state, &mut spill);
+        } else if let Some(path) = &self.checkpoint {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        if let Some(mut spill) = spill {
+            state.output.results.extend(spill.read_all()?);
+            if !keep_spill {
+                spill.remove()?;
+            }
+        }
+
+        let mut output = state.output;
+        output.partial = partial;
+        output.finish(self.graph_metrics);
+        Ok(output)
+    }
+
+    fn save_checkpoint(&self, state: &mut Checkpoint, spill: &mut Option<SpillBuffer>) {
+        if let Some(path) = &self.checkpoint {
+            if let Some(spill) = spill {
+                if let Err(e) = spill.flush() {
+                    warn!("Failed to write spilled results: {e}");
+                }
+                state.spill = Some(spill.path().to_path_buf());
+            }
+            if let Err(e) = state.save(path) {
+                warn!("Failed to save checkpoint: {e}");
+            }
+        }
+    }
+
+    /// Next to the checkpoint, so that spilled results are available when resuming
+    fn spill_path(&self) -> PathBuf {
+        match &self.checkpoint {
+            Some(checkpoint) => checkpoint.with_extension("spill"),
+            None => std::env::temp_dir()
+                .join(format!("lemmy-stats-crawler-{}.spill", std::process::id())),
+        }
+    }
+}
+
+#[deprecated(note = "use `Crawler` instead")]
+pub async fn start_crawl(
+    start_instances: Vec<String>,
+    exclude_domains: Vec<String>,
+    jobs_count: u32,
+    max_distance: u8,
+    timeout: Duration,
+) -> Result<CrawlOutput, Error> {
+    Crawler::new()
+        .start_instances(start_instances)
+        .exclude_domains(exclude_domains)
+        .jobs(jobs_count)
+        .max_distance(max_distance)
+        .timeout(timeout)
+        .run()
+        .await
+}
+
+/// Worker tasks of a crawl, which are stopped when the crawl ends, also if it fails
+struct Workers {
+    handles: Vec<JoinHandle<()>>,
+    queue: Arc<JobQueue>,
+}
+
+impl Workers {
+    /// Close the queue and wait until all workers have stopped
+    async fn join(mut self) {
+        self.queue.close();
+        for handle in self.handles.drain(..) {
+            // aborted workers return an error, which is expected
+            handle.await.ok();
+        }
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        self.queue.close();
+        self.handles.iter().for_each(JoinHandle::abort);
+    }
+}
+
+async fn background_task(i: u32, queue: Arc<JobQueue>) {
+    while let Some(job) = queue.pop().await {
+        let domain = job.domain.clone();
+        debug!(
+            "Worker {i} starting job {domain} at distance {}",
+            job.current_distance
+        );
+        let res = job.crawl(&queue).await;
+        queue.finish(&domain);
+        if let Err(e) = res {
+            trace!("Job {domain} errored with: {}", e)
+        }
+    }
+}
+
+/// Which Lemmy versions are crawled
+#[derive(Debug, Clone)]
+pub enum MinVersion {
+    /// One minor version before the latest Lemmy release
+    Auto,
+    /// Given version or newer
+    Fixed(Version),
+    /// All versions, including those which can only be counted with reduced detail
+    Any,
+}
+
+impl FromStr for MinVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(MinVersion::Auto),
+            "any" => Ok(MinVersion::Any),
+            _ => Ok(MinVersion::Fixed(Version::parse(s)?)),
         }
     }
 }
@@ -118,3 +1022,39 @@ async fn min_lemmy_version(client: &ClientWithMiddleware) -> Result<Version, Err
     version.minor -= 1;
     Ok(version)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Handle;
+
+    #[tokio::test]
+    async fn workers_stop_after_crawl() -> Result<(), Error> {
+        let crawler = Crawler::new()
+            .start_instances(vec![])
+            .min_version(MinVersion::Any)
+            .jobs(10);
+        // With the http3 feature, the client runs a task for its QUIC endpoint, which is only
+        // started by the first run
+        crawler.run().await?;
+        let metrics = Handle::current().metrics();
+        let baseline = metrics.num_alive_tasks();
+        let output = crawler.run().await?;
+        assert!(output.results.is_empty());
+        assert_eq!(metrics.num_alive_tasks(), baseline);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn clones_reuse_clients() -> Result<(), Error> {
+        let crawler = Crawler::new()
+            .start_instances(vec![])
+            .min_version(MinVersion::Any);
+        let clone = crawler.clone();
+        crawler.run().await?;
+        assert!(clone.clients.get().is_some());
+        let changed = clone.timeout(Duration::from_secs(1));
+        assert!(changed.clients.get().is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use crate::crawl::CrawlResult;
+use anyhow::Error;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often memory usage is checked against the limit
+pub const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Resident memory of this process in bytes. Only available on Linux.
+pub fn resident_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kb: u64 = status
+        .lines()
+        .find_map(|l| l.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// Crawl results written to disk as JSON lines, instead of being kept in memory
+pub struct SpillBuffer {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl SpillBuffer {
+    /// Appends to the file if it exists, eg when resuming a crawl
+    pub fn open(path: PathBuf) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(SpillBuffer {
+            path,
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn push(&mut self, result: &CrawlResult) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.writer, result)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Read back all results
+    pub fn read_all(&mut self) -> Result<Vec<CrawlResult>, Error> {
+        self.flush()?;
+        BufReader::new(File::open(&self.path)?)
+            .lines()
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    }
+
+    pub fn remove(self) -> Result<(), Error> {
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Domains of the results in a spill file, without keeping the results in memory
+pub fn spilled_domains(path: &Path) -> Result<HashSet<String>, Error> {
+    #[derive(Deserialize)]
+    struct Domain {
+        domain: String,
+    }
+    BufReader::new(File::open(path)?)
+        .lines()
+        .map(|line| Ok(serde_json::from_str::<Domain>(&line?)?.domain))
+        .collect()
+}
@@ -0,0 +1,118 @@
+use crate::aggregate::TotalStats;
+use anyhow::Error;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// Values of the latest crawl in Prometheus text format
+#[derive(Debug, Default)]
+pub struct CrawlMetrics {
+    rendered: RwLock<String>,
+}
+
+impl CrawlMetrics {
+    pub fn update(&self, stats: &TotalStats, crawl_duration: Duration) {
+        let gauges = [
+            (
+                "lemmy_stats_crawled_instances",
+                "Number of crawled Lemmy instances",
+                stats.crawled_instances as f64,
+            ),
+            (
+                "lemmy_stats_failed_instances",
+                "Number of instances which failed to crawl",
+                stats.failed_instances.len() as f64,
+            ),
+            (
+                "lemmy_stats_total_users",
+                "Total number of users",
+                stats.total_users as f64,
+            ),
+            (
+                "lemmy_stats_users_active_day",
+                "Users active in the last day",
+                stats.users_active_day as f64,
+            ),
+            (
+                "lemmy_stats_users_active_week",
+                "Users active in the last week",
+                stats.users_active_week as f64,
+            ),
+            (
+                "lemmy_stats_users_active_month",
+                "Users active in the last month",
+                stats.users_active_month as f64,
+            ),
+            (
+                "lemmy_stats_users_active_halfyear",
+                "Users active in the last half year",
+                stats.users_active_halfyear as f64,
+            ),
+            (
+                "lemmy_stats_crawl_duration_seconds",
+                "Duration of the last crawl",
+                crawl_duration.as_secs_f64(),
+            ),
+        ];
+        let mut rendered = String::new();
+        for (name, help, value) in gauges {
+            // writing to a string can't fail
+            let _ = writeln!(rendered, "# HELP {name} {help}");
+            let _ = writeln!(rendered, "# TYPE {name} gauge");
+            let _ = writeln!(rendered, "{name} {value}");
+        }
+        let _ = writeln!(
+            rendered,
+            "# HELP lemmy_stats_crawl_info Run id of the last crawl"
+        );
+        let _ = writeln!(rendered, "# TYPE lemmy_stats_crawl_info gauge");
+        let _ = writeln!(
+            rendered,
+            "lemmy_stats_crawl_info{{run_id=\"{}\"}} 1",
+            stats.run_id
+        );
+        *self.rendered.write().unwrap() = rendered;
+    }
+}
+
+/// Serve the metrics at `/metrics`, until the process is stopped. The listener is bound by the
+/// caller, so that an unavailable address is reported before crawling.
+pub async fn serve_metrics(listener: TcpListener, metrics: Arc<CrawlMetrics>) -> Result<(), Error> {
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(metrics);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn metrics_handler(State(metrics): State<Arc<CrawlMetrics>>) -> impl IntoResponse {
+    let rendered = metrics.rendered.read().unwrap().clone();
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        rendered,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn address_is_taken_before_serving() -> Result<(), Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        assert!(TcpListener::bind(addr).await.is_err());
+
+        let server = tokio::spawn(serve_metrics(listener, Arc::default()));
+        let response = reqwest::get(format!("http://{addr}/metrics")).await?;
+        assert!(response.status().is_success());
+        server.abort();
+        Ok(())
+    }
+}
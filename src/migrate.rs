@@ -0,0 +1,108 @@
+use crate::output::decompress;
+use anyhow::{anyhow, bail, Error};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+/// Version of the JSON output format. Needs to be increased together with a migration step
+/// below whenever fields are renamed, removed or change their type. New optional fields don't
+/// need a migration, readers treat them as null when missing.
+pub const SCHEMA_VERSION: u64 = 2;
+
+/// Read an output file of any crawler version, and upgrade it to the current schema. The file
+/// can be compressed with gzip or zstd.
+pub fn load_output(path: &Path) -> Result<Value, Error> {
+    let output = serde_json::from_slice(&decompress(fs::read(path)?)?)?;
+    migrate(output)
+}
+
+/// Upgrade JSON output written by an older crawler version to the current schema.
+pub fn migrate(mut output: Value) -> Result<Value, Error> {
+    let object = output
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("crawler output must be a JSON object"))?;
+    let version = object
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    if version > SCHEMA_VERSION {
+        bail!("schema version {version} is newer than supported version {SCHEMA_VERSION}");
+    }
+
+    if version < 1 {
+        // Added failed instances, contact details and federated instances errors
+        object
+            .entry("failed_instances")
+            .or_insert_with(|| json!([]));
+        for i in instances_mut(object) {
+            i.entry("federated_instances_error").or_insert(Value::Null);
+            i.entry("contact").or_insert(Value::Null);
+        }
+    }
+
+    if version < 2 {
+        // PieFed site info is nested under `piefed`, so that it isn't read as Lemmy 0.18
+        for i in instances_mut(object) {
+            if i.get("software").and_then(Value::as_str) == Some("piefed") {
+                if let Some(site_info) = i.remove("site_info") {
+                    i.insert("site_info".to_string(), json!({ "piefed": site_info }));
+                }
+            }
+        }
+    }
+
+    object.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    Ok(output)
+}
+
+fn instances_mut(
+    object: &mut serde_json::Map<String, Value>,
+) -> impl Iterator<Item = &mut serde_json::Map<String, Value>> {
+    object
+        .get_mut("instance_details")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object_mut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrades_version_0() -> Result<(), Error> {
+        let output = migrate(json!({
+            "instance_details": [{"domain": "lemmy.ml", "software": "lemmy", "site_info": {}}]
+        }))?;
+        assert_eq!(output["schema_version"], SCHEMA_VERSION);
+        assert_eq!(output["failed_instances"], json!([]));
+        let instance = &output["instance_details"][0];
+        assert_eq!(instance["contact"], Value::Null);
+        assert_eq!(instance["federated_instances_error"], Value::Null);
+        assert_eq!(instance["site_info"], json!({}));
+        Ok(())
+    }
+
+    #[test]
+    fn nests_piefed_site_info() -> Result<(), Error> {
+        let output = migrate(json!({
+            "schema_version": 1,
+            "failed_instances": [],
+            "instance_details": [{"domain": "piefed.social", "software": "piefed", "site_info": {"version": "1.0"}}]
+        }))?;
+        assert_eq!(
+            output["instance_details"][0]["site_info"],
+            json!({"piefed": {"version": "1.0"}})
+        );
+        // Already migrated output is left unchanged
+        assert_eq!(migrate(output.clone())?, output);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_newer_schema() {
+        let output = json!({"schema_version": SCHEMA_VERSION + 1});
+        assert!(migrate(output).is_err());
+    }
+}
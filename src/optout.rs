@@ -0,0 +1,165 @@
+use anyhow::{anyhow, Error};
+use std::str::FromStr;
+
+/// User agent which instances can disallow in robots.txt to opt out of detailed data collection
+pub const CRAWLER_USER_AGENT: &str = "lemmy-stats-crawler";
+
+/// What to record for instances which opted out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptOutMode {
+    /// Only user counts from nodeinfo
+    Counts,
+    /// Nothing at all
+    Skip,
+}
+
+impl FromStr for OptOutMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "counts" => Ok(OptOutMode::Counts),
+            "skip" => Ok(OptOutMode::Skip),
+            _ => Err(anyhow!(
+                "unsupported opt-out mode {s}, expected counts or skip"
+            )),
+        }
+    }
+}
+
+/// Rules of a robots.txt which apply to this crawler. Only groups for the crawler's own user
+/// agent count, as many instances disallow `*` to keep search engines away.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RobotsTxt {
+    rules: Vec<RobotsRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RobotsRule {
+    allow: bool,
+    /// Path prefix, which can contain `*` wildcards and end with `$` to match the whole path
+    pattern: String,
+}
+
+impl RobotsTxt {
+    pub fn parse(robots_txt: &str) -> Self {
+        let mut rules = vec![];
+        let mut group_matches = false;
+        let mut in_agent_lines = false;
+        for line in robots_txt.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((field, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match field.trim().to_lowercase().as_str() {
+                "user-agent" => {
+                    // consecutive user-agent lines share the rules which follow them
+                    if !in_agent_lines {
+                        group_matches = false;
+                    }
+                    in_agent_lines = true;
+                    group_matches |= value.eq_ignore_ascii_case(CRAWLER_USER_AGENT);
+                }
+                field @ ("allow" | "disallow") => {
+                    in_agent_lines = false;
+                    // An empty disallow allows everything
+                    if group_matches && !value.is_empty() {
+                        rules.push(RobotsRule {
+                            allow: field == "allow",
+                            pattern: value.to_string(),
+                        });
+                    }
+                }
+                _ => in_agent_lines = false,
+            }
+        }
+        RobotsTxt { rules }
+    }
+
+    /// Whether the crawler may fetch the path, eg `/api/v3/site`. The most specific matching rule
+    /// decides, with allow winning ties.
+    pub fn allows(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|r| pattern_matches(&r.pattern, path))
+            .max_by_key(|r| (r.pattern.len(), r.allow))
+            .map_or(true, |r| r.allow)
+    }
+}
+
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let mut parts = pattern.split('*');
+    let Some(prefix) = parts.next() else {
+        return true;
+    };
+    let Some(mut rest) = path.strip_prefix(prefix) else {
+        return false;
+    };
+    let parts: Vec<_> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        // The last part of an anchored pattern needs to match the end of the path
+        if anchored && i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(position) => rest = &rest[position + part.len()..],
+            None => return false,
+        }
+    }
+    !anchored || rest.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_own_user_agent_counts() {
+        let robots = RobotsTxt::parse(
+            "User-agent: *\nDisallow: /\n\nUser-agent: Googlebot\nDisallow: /api/\n",
+        );
+        assert!(robots.allows("/api/v3/site"));
+    }
+
+    #[test]
+    fn disallow_everything() {
+        let robots = RobotsTxt::parse(
+            "User-agent: Googlebot\nUser-Agent: lemmy-stats-crawler # stats\nDisallow: /\n",
+        );
+        assert!(!robots.allows("/api/v3/site"));
+        assert!(!robots.allows("/"));
+    }
+
+    #[test]
+    fn path_prefixes() {
+        let robots = RobotsTxt::parse(
+            "User-agent: lemmy-stats-crawler\nDisallow: /api/\nAllow: /api/v3/site\nDisallow:\n",
+        );
+        assert!(robots.allows("/api/v3/site"));
+        assert!(!robots.allows("/api/v3/federated_instances"));
+        assert!(robots.allows("/nodeinfo/2.1"));
+    }
+
+    #[test]
+    fn empty_disallow_allows_everything() {
+        let robots = RobotsTxt::parse("User-agent: lemmy-stats-crawler\nDisallow:\n");
+        assert_eq!(robots, RobotsTxt::default());
+        assert!(robots.allows("/api/v3/site"));
+    }
+
+    #[test]
+    fn wildcards() {
+        let robots = RobotsTxt::parse(
+            "User-agent: lemmy-stats-crawler\nDisallow: /api/*/community\nDisallow: /*.json$\n",
+        );
+        assert!(!robots.allows("/api/v3/community/list"));
+        assert!(robots.allows("/api/v3/site"));
+        assert!(!robots.allows("/nodeinfo/2.0.json"));
+        assert!(robots.allows("/nodeinfo/2.0.json/more"));
+    }
+}
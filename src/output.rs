@@ -0,0 +1,156 @@
+use crate::crawl::CrawlResult;
+use crate::rollup::RollupRow;
+use anyhow::{anyhow, Error};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use lemmy_api_common_v019::lemmy_db_schema::RegistrationMode;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Flat summary of an instance, used for CSV output and queries
+#[derive(Serialize)]
+pub(crate) struct InstanceRow<'a> {
+    domain: &'a str,
+    version: String,
+    total_users: i64,
+    users_active_day: i64,
+    users_active_week: i64,
+    users_active_month: i64,
+    users_active_halfyear: i64,
+    posts: i64,
+    comments: i64,
+    registration_mode: RegistrationMode,
+}
+
+impl<'a> InstanceRow<'a> {
+    pub(crate) fn new(i: &'a CrawlResult) -> Self {
+        InstanceRow {
+            domain: &i.domain,
+            version: i.site_info.version(),
+            total_users: i.site_info.total_users(),
+            users_active_day: i.site_info.users_active_day(),
+            users_active_week: i.site_info.users_active_week(),
+            users_active_month: i.site_info.users_active_month(),
+            users_active_halfyear: i.site_info.users_active_half_year(),
+            posts: i.site_info.posts(),
+            comments: i.site_info.comments(),
+            registration_mode: i.site_info.registration_mode(),
+        }
+    }
+}
+
+/// Write one CSV row per instance, with a header row
+pub fn write_csv(results: &[CrawlResult], writer: impl Write) -> Result<(), Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for i in results {
+        writer.serialize(InstanceRow::new(i))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write one CSV row per period and instance, with a header row
+pub fn write_rollup_csv(rows: &[RollupRow], writer: impl Write) -> Result<(), Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Compression of output files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(anyhow!(
+                "unsupported compression {s}, expected gzip or zstd"
+            )),
+        }
+    }
+}
+
+impl Compression {
+    pub fn compress(self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(content)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Zstd => Ok(zstd::encode_all(content, 0)?),
+        }
+    }
+
+    /// File extension for compressed files, without leading dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+}
+
+/// Decompress gzip or zstd content, detected by its magic bytes. Other content is returned
+/// unchanged.
+pub fn decompress(content: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let mut decompressed = vec![];
+    if content.starts_with(&[0x1f, 0x8b]) {
+        GzDecoder::new(content.as_slice()).read_to_end(&mut decompressed)?;
+    } else if content.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        decompressed = zstd::decode_all(content.as_slice())?;
+    } else {
+        return Ok(content);
+    }
+    Ok(decompressed)
+}
+
+/// Write to a temporary file next to the target and rename it once complete, so that readers
+/// never see a partially written file
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<(), Error> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("invalid output file {}", path.display()))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(".tmp");
+    let temp = path.with_file_name(temp_name);
+    fs::write(&temp, content)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+/// Serialize with object keys sorted and without whitespace, so that the same data always results
+/// in the same bytes, eg for hashing or signing
+pub fn to_canonical_json<T: Serialize>(value: &T) -> Result<String, Error> {
+    Ok(serde_json::to_string(&sort_keys(serde_json::to_value(
+        value,
+    )?))?)
+}
+
+fn sort_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let sorted: BTreeMap<_, _> = map.into_iter().map(|(k, v)| (k, sort_keys(v))).collect();
+            Value::Object(sorted.into_iter().collect())
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sort_keys).collect()),
+        value => value,
+    }
+}
@@ -0,0 +1,296 @@
+use crate::aggregate::TotalStats;
+use crate::directory::InstanceDirectoryEntry;
+use crate::graph::{xml_escape, EdgeKind, FederationGraph};
+use crate::rollup::Sample;
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Data for a page about a single instance, comparing it with the rest of the network
+#[derive(Debug, Serialize)]
+pub struct InstancePage {
+    #[serde(flatten)]
+    pub directory: InstanceDirectoryEntry,
+    pub version: String,
+    pub total_users: i64,
+    pub users_active_month: i64,
+    /// Position by monthly active users, starting at 1
+    pub rank: usize,
+    /// Fraction of all monthly active users in the network
+    pub share_of_active_users: f64,
+    /// Counts from each stored crawl, oldest first. Empty without a history store.
+    pub history: Vec<HistoryPoint>,
+    pub neighbors: Neighbors,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryPoint {
+    pub crawled_at: DateTime<Utc>,
+    pub total_users: i64,
+    pub users_active_month: i64,
+}
+
+/// Crawled instances which this instance federates with, or which federate with it
+#[derive(Debug, Default, Serialize)]
+pub struct Neighbors {
+    pub linked: BTreeSet<String>,
+    pub linked_by: BTreeSet<String>,
+    pub blocked: BTreeSet<String>,
+    pub blocked_by: BTreeSet<String>,
+}
+
+/// Build a page for each crawled instance, with history from the given samples of a store
+pub fn instance_pages(stats: &TotalStats, history: Vec<Sample>) -> Vec<InstancePage> {
+    let mut history_by_domain: HashMap<String, Vec<HistoryPoint>> = HashMap::new();
+    for s in history {
+        if let Some(domain) = s.domain {
+            history_by_domain
+                .entry(domain)
+                .or_default()
+                .push(HistoryPoint {
+                    crawled_at: s.crawled_at,
+                    total_users: s.total_users,
+                    users_active_month: s.users_active_month,
+                });
+        }
+    }
+    let mut neighbors: HashMap<String, Neighbors> = HashMap::new();
+    for edge in FederationGraph::new(&stats.instance_details).edges {
+        let (outgoing, incoming) = (edge.source.clone(), edge.target.clone());
+        match edge.kind {
+            EdgeKind::Linked => {
+                neighbors
+                    .entry(edge.source)
+                    .or_default()
+                    .linked
+                    .insert(incoming);
+                neighbors
+                    .entry(edge.target)
+                    .or_default()
+                    .linked_by
+                    .insert(outgoing);
+            }
+            EdgeKind::Blocked => {
+                neighbors
+                    .entry(edge.source)
+                    .or_default()
+                    .blocked
+                    .insert(incoming);
+                neighbors
+                    .entry(edge.target)
+                    .or_default()
+                    .blocked_by
+                    .insert(outgoing);
+            }
+            EdgeKind::Allowed => {}
+        }
+    }
+
+    // instance details are sorted by monthly active users already
+    stats
+        .instance_details
+        .iter()
+        .enumerate()
+        .map(|(i, r)| {
+            let mut history = history_by_domain.remove(&r.domain).unwrap_or_default();
+            history.sort_by_key(|h| h.crawled_at);
+            let users_active_month = r.site_info.users_active_month();
+            InstancePage {
+                directory: InstanceDirectoryEntry::new(r),
+                version: r.site_info.version(),
+                total_users: r.site_info.total_users(),
+                users_active_month,
+                rank: i + 1,
+                share_of_active_users: users_active_month as f64
+                    / stats.users_active_month.max(1) as f64,
+                history,
+                neighbors: neighbors.remove(&r.domain).unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+/// Size of the history sparkline in pixels
+const SPARKLINE_WIDTH: f64 = 300.0;
+const SPARKLINE_HEIGHT: f64 = 60.0;
+
+impl InstancePage {
+    /// Static HTML page, which links to the pages of neighbors in the same directory
+    pub fn to_html(&self, instances: usize) -> String {
+        let domain = xml_escape(&self.directory.domain);
+        let name = xml_escape(&self.directory.name);
+        let mut html = format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{name} ({domain})</title>
+</head>
+<body>
+<p><a href="index.html">All instances</a></p>
+<h1>{name}</h1>
+<p><a href="https://{domain}/">{domain}</a>, Lemmy {}</p>
+"#,
+            xml_escape(&self.version)
+        );
+        if let Some(description) = &self.directory.description {
+            let _ = writeln!(html, "<p>{}</p>", xml_escape(description));
+        }
+        let _ = writeln!(
+            html,
+            "<table>
+<tr><th>Monthly active users</th><td>{}</td></tr>
+<tr><th>Total users</th><td>{}</td></tr>
+<tr><th>Rank</th><td>{} of {instances}</td></tr>
+<tr><th>Share of active users</th><td>{:.2}%</td></tr>
+</table>",
+            self.users_active_month,
+            self.total_users,
+            self.rank,
+            self.share_of_active_users * 100.0
+        );
+        html.push_str("<h2>Monthly active users</h2>\n");
+        html.push_str(&sparkline(&self.history));
+        html.push_str("<h2>Federation</h2>\n");
+        for (title, domains) in [
+            ("Links to", &self.neighbors.linked),
+            ("Linked by", &self.neighbors.linked_by),
+            ("Blocks", &self.neighbors.blocked),
+            ("Blocked by", &self.neighbors.blocked_by),
+        ] {
+            let _ = writeln!(html, "<h3>{title} ({})</h3>", domains.len());
+            html.push_str(&domain_list(domains.iter()));
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+/// SVG line of the monthly active users over time, scaled to the maximum
+fn sparkline(history: &[HistoryPoint]) -> String {
+    if history.len() < 2 {
+        return "<p>Not enough history yet</p>\n".to_string();
+    }
+    let max = history
+        .iter()
+        .map(|h| h.users_active_month)
+        .max()
+        .unwrap_or_default()
+        .max(1) as f64;
+    let step = SPARKLINE_WIDTH / (history.len() - 1) as f64;
+    let points = history
+        .iter()
+        .enumerate()
+        .fold(String::new(), |mut points, (i, h)| {
+            let y = SPARKLINE_HEIGHT - h.users_active_month as f64 / max * SPARKLINE_HEIGHT;
+            let _ = write!(points, "{:.1},{y:.1} ", i as f64 * step);
+            points
+        });
+    format!(
+        r#"<svg width="{SPARKLINE_WIDTH}" height="{SPARKLINE_HEIGHT}" viewBox="0 0 {SPARKLINE_WIDTH} {SPARKLINE_HEIGHT}"><polyline fill="none" stroke="currentColor" points="{}"/></svg>
+"#,
+        points.trim_end()
+    )
+}
+
+/// List of links to the pages of the instances
+fn domain_list<'a>(domains: impl Iterator<Item = &'a String>) -> String {
+    let mut html = String::from("<ul>\n");
+    for domain in domains {
+        let domain = xml_escape(domain);
+        let _ = writeln!(html, r#"<li><a href="{domain}.html">{domain}</a></li>"#);
+    }
+    html.push_str("</ul>\n");
+    html
+}
+
+/// Page which links to the pages of all instances, in the order of the pages
+fn index_html(pages: &[InstancePage]) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Lemmy instances</title>
+</head>
+<body>
+<h1>Lemmy instances</h1>
+{}</body>
+</html>
+"#,
+        domain_list(pages.iter().map(|p| &p.directory.domain))
+    )
+}
+
+/// Write each page as `<domain>.json` and `<domain>.html` into the directory, creating it if
+/// necessary, together with an `index.html` which links to all pages
+pub fn write_instance_pages(pages: &[InstancePage], dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+    for page in pages {
+        let domain = &page.directory.domain;
+        fs::write(
+            dir.join(format!("{domain}.json")),
+            serde_json::to_string_pretty(page)?,
+        )?;
+        fs::write(
+            dir.join(format!("{domain}.html")),
+            page.to_html(pages.len()),
+        )?;
+    }
+    fs::write(dir.join("index.html"), index_html(pages))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lemmy_api_common_v019::lemmy_db_schema::RegistrationMode;
+
+    fn page(history: Vec<HistoryPoint>) -> InstancePage {
+        InstancePage {
+            directory: InstanceDirectoryEntry {
+                domain: "example.com".to_string(),
+                name: "<Example>".to_string(),
+                description: None,
+                icon: None,
+                languages: vec![],
+                registration_mode: RegistrationMode::Open,
+            },
+            version: "0.19.5".to_string(),
+            total_users: 100,
+            users_active_month: 10,
+            rank: 2,
+            share_of_active_users: 0.25,
+            history,
+            neighbors: Neighbors {
+                linked: BTreeSet::from(["lemmy.ml".to_string()]),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn html_page_escapes_and_links_neighbors() {
+        let html = page(vec![]).to_html(3);
+        assert!(html.contains("<h1>&lt;Example&gt;</h1>"));
+        assert!(html.contains("<td>2 of 3</td>"));
+        assert!(html.contains("<td>25.00%</td>"));
+        assert!(html.contains(r#"<a href="lemmy.ml.html">lemmy.ml</a>"#));
+        assert!(html.contains("Not enough history yet"));
+    }
+
+    #[test]
+    fn sparkline_scales_to_maximum() {
+        let point = |users_active_month| HistoryPoint {
+            crawled_at: Utc::now(),
+            total_users: 0,
+            users_active_month,
+        };
+        let svg = sparkline(&[point(0), point(10), point(5)]);
+        assert!(svg.contains(r#"points="0.0,60.0 150.0,0.0 300.0,30.0""#));
+    }
+}
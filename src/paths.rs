@@ -0,0 +1,79 @@
+use anyhow::{anyhow, Error};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Key which matches every path, to prepend a prefix for instances under a subpath
+const ANY_PATH: &str = "*";
+
+/// Replaces the standard path of an endpoint, in the form `[domain:]path=replacement`. Without
+/// a domain the override applies to all instances. The path `*` adds a prefix to all paths, eg
+/// `example.com:*=lemmy` requests `lemmy/api/v3/site` instead of `api/v3/site`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathOverride {
+    pub domain: Option<String>,
+    pub path: String,
+    pub replacement: String,
+}
+
+impl FromStr for PathOverride {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, replacement) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("invalid path override {s}, expected [domain:]path=path"))?;
+        let (domain, path) = match key.split_once(':') {
+            Some((domain, path)) => (Some(domain.to_string()), path),
+            None => (None, key),
+        };
+        Ok(PathOverride {
+            domain,
+            path: path.trim_matches('/').to_string(),
+            replacement: replacement.trim_matches('/').to_string(),
+        })
+    }
+}
+
+/// Path overrides by domain, with `None` for those which apply to all instances
+#[derive(Debug, Clone, Default)]
+pub struct PathOverrides(HashMap<Option<String>, HashMap<String, String>>);
+
+impl PathOverrides {
+    pub(crate) fn new(overrides: Vec<PathOverride>) -> Self {
+        let mut map: HashMap<_, HashMap<_, _>> = HashMap::new();
+        for o in overrides {
+            map.entry(o.domain)
+                .or_default()
+                .insert(o.path, o.replacement);
+        }
+        PathOverrides(map)
+    }
+
+    /// Path to request from the domain. Exact overrides take precedence over prefixes, and
+    /// overrides for the domain over those for all instances. The query string is kept.
+    pub(crate) fn resolve(&self, domain: &str, path: &str) -> String {
+        if self.0.is_empty() {
+            return path.to_string();
+        }
+        let (path_only, query) = match path.split_once('?') {
+            Some((path_only, query)) => (path_only, Some(query)),
+            None => (path, None),
+        };
+        let domain_overrides = self.0.get(&Some(domain.to_string()));
+        let global_overrides = self.0.get(&None);
+        let lookup = |key: &str| {
+            domain_overrides
+                .and_then(|o| o.get(key))
+                .or_else(|| global_overrides.and_then(|o| o.get(key)))
+        };
+        let resolved = match (lookup(path_only), lookup(ANY_PATH)) {
+            (Some(replacement), _) => replacement.clone(),
+            (None, Some(prefix)) => format!("{prefix}/{path_only}"),
+            (None, None) => path_only.to_string(),
+        };
+        match query {
+            Some(query) => format!("{resolved}?{query}"),
+            None => resolved,
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{anyhow, Error};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Ordered allow and deny rules for discovered domains. The first matching rule decides, domains
+/// which match no rule are allowed.
+#[derive(Debug, Clone, Default)]
+pub struct DomainPolicy {
+    rules: Vec<PolicyRule>,
+}
+
+impl DomainPolicy {
+    pub fn new(rules: Vec<PolicyRule>) -> Self {
+        DomainPolicy { rules }
+    }
+
+    /// Read rules from a file with one rule per line, eg `deny suffix:.xyz`. Empty lines and
+    /// lines starting with `#` are ignored.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let rules = fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(PolicyRule::from_str)
+            .collect::<Result<_, _>>()?;
+        Ok(DomainPolicy { rules })
+    }
+
+    /// Deny these domains before all other rules
+    pub fn with_excluded(mut self, domains: Vec<String>) -> Self {
+        let excluded = domains.into_iter().map(|d| PolicyRule {
+            action: PolicyAction::Deny,
+            pattern: DomainPattern::Exact(d),
+        });
+        self.rules.splice(0..0, excluded);
+        self
+    }
+
+    pub fn allows(&self, domain: &str) -> bool {
+        self.rules
+            .iter()
+            .find(|r| r.pattern.matches(domain))
+            .map_or(true, |r| r.action == PolicyAction::Allow)
+    }
+}
+
+/// Rule in the form `<allow|deny> <kind>:<pattern>`, see `DomainPattern` for the kinds
+#[derive(Debug, Clone)]
+pub struct PolicyRule {
+    pub action: PolicyAction,
+    pub pattern: DomainPattern,
+}
+
+impl FromStr for PolicyRule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (action, pattern) = s
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| anyhow!("invalid rule {s}, expected <allow|deny> <kind>:<pattern>"))?;
+        let action = match action {
+            "allow" => PolicyAction::Allow,
+            "deny" => PolicyAction::Deny,
+            _ => return Err(anyhow!("invalid action {action}, expected allow or deny")),
+        };
+        Ok(PolicyRule {
+            action,
+            pattern: pattern.trim().parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone)]
+pub enum DomainPattern {
+    /// `exact:example.com` matches only that domain
+    Exact(String),
+    /// `suffix:.xyz` matches all domains ending with the text
+    Suffix(String),
+    /// `apex:example.com` matches the domain and all its subdomains
+    Apex(String),
+    /// `regex:^test[0-9]+\.` matches domains which contain a match of the regex
+    Regex(Regex),
+}
+
+impl DomainPattern {
+    fn matches(&self, domain: &str) -> bool {
+        match self {
+            DomainPattern::Exact(exact) => domain == exact,
+            DomainPattern::Suffix(suffix) => domain.ends_with(suffix.as_str()),
+            DomainPattern::Apex(apex) => {
+                domain == apex
+                    || domain
+                        .strip_suffix(apex.as_str())
+                        .map_or(false, |sub| sub.ends_with('.'))
+            }
+            DomainPattern::Regex(regex) => regex.is_match(domain),
+        }
+    }
+}
+
+impl FromStr for DomainPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("exact", p)) => Ok(DomainPattern::Exact(p.to_string())),
+            Some(("suffix", p)) => Ok(DomainPattern::Suffix(p.to_string())),
+            Some(("apex", p)) => Ok(DomainPattern::Apex(p.to_string())),
+            Some(("regex", p)) => Ok(DomainPattern::Regex(Regex::new(p)?)),
+            _ => Err(anyhow!(
+                "invalid pattern {s}, expected exact, suffix, apex or regex followed by :"
+            )),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use crate::crawl::CrawlOutcome;
+use crate::{CrawlOutput, Crawler};
+use anyhow::Error;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// Retries instances between full crawls, which were crawled successfully before but are missing
+/// in the latest crawl. Instances which recover are added to the latest results, so that short
+/// outages don't show up as instances leaving and joining again.
+#[derive(Debug)]
+pub struct KeepAlive {
+    /// Output of the latest full crawl, with recovered instances added
+    output: CrawlOutput,
+    /// Instances which are probed, with the time when they went missing
+    missing: HashMap<String, Instant>,
+    /// Missing instances are not probed anymore after this time
+    max_age: Duration,
+}
+
+impl KeepAlive {
+    pub fn new(max_age: Duration) -> Self {
+        KeepAlive {
+            output: CrawlOutput::default(),
+            missing: HashMap::new(),
+            max_age,
+        }
+    }
+
+    /// Keep the output of a full crawl, and start probing instances from the previous crawl which
+    /// are missing in it. Partial crawls are ignored.
+    pub fn update(&mut self, output: CrawlOutput) {
+        if output.partial {
+            return;
+        }
+        let crawled: HashSet<&str> = output.results.iter().map(|r| r.domain.as_str()).collect();
+        let now = Instant::now();
+        for result in &self.output.results {
+            if !crawled.contains(result.domain.as_str()) {
+                self.missing.entry(result.domain.clone()).or_insert(now);
+            }
+        }
+        let max_age = self.max_age;
+        self.missing.retain(|domain, since| {
+            !crawled.contains(domain.as_str()) && since.elapsed() < max_age
+        });
+        self.output = output;
+    }
+
+    /// Crawl the missing instances, without discovering new ones. Returns the latest output with
+    /// recovered instances, or `None` if none of them recovered.
+    pub async fn probe(&mut self, crawler: &Crawler) -> Result<Option<CrawlOutput>, Error> {
+        if self.missing.is_empty() {
+            return Ok(None);
+        }
+        let probe = crawler
+            .clone()
+            .start_instances(self.missing.keys().cloned().collect())
+            .max_distance(0)
+            .checkpoint(None)
+            .progress(None)
+            .run()
+            .await?;
+        if probe.results.is_empty() {
+            return Ok(None);
+        }
+        for result in probe.results {
+            self.missing.remove(&result.domain);
+            self.output.failures.retain(|f| f.domain != result.domain);
+            let outcome = CrawlOutcome::Success(Box::new(result));
+            self.output.totals.add(&outcome);
+            self.output.add(outcome);
+        }
+        self.output.finish(crawler.graph_metrics);
+        Ok(Some(self.output.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawl::tests::result_json;
+    use crate::crawl::CrawlResult;
+
+    fn output(domains: &[&str], partial: bool) -> CrawlOutput {
+        let results = domains
+            .iter()
+            .map(|domain| {
+                let mut json = result_json();
+                json["domain"] = (*domain).into();
+                serde_json::from_value::<CrawlResult>(json).unwrap()
+            })
+            .collect();
+        CrawlOutput {
+            results,
+            partial,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn probes_instances_missing_from_full_crawls() {
+        let mut keep_alive = KeepAlive::new(Duration::from_secs(60));
+        keep_alive.update(output(&["a.example", "b.example"], false));
+        assert!(keep_alive.missing.is_empty());
+        // Partial crawls don't count
+        keep_alive.update(output(&["a.example"], true));
+        assert!(keep_alive.missing.is_empty());
+        keep_alive.update(output(&["a.example"], false));
+        assert!(keep_alive.missing.contains_key("b.example"));
+        assert_eq!(keep_alive.output.results.len(), 1);
+        keep_alive.update(output(&["a.example", "b.example"], false));
+        assert!(keep_alive.missing.is_empty());
+    }
+}
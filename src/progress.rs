@@ -0,0 +1,32 @@
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// How often progress is reported while crawling
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// State of a running crawl, sent periodically to the channel set with `Crawler::progress`
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CrawlProgress {
+    pub queued_jobs: usize,
+    /// Jobs which are being crawled right now
+    pub running_jobs: usize,
+    /// Including instances with private API or which opted out
+    pub crawled_instances: i32,
+    pub failed_instances: usize,
+    pub elapsed: Duration,
+}
+
+impl Display for CrawlProgress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}s elapsed, {} crawled, {} failed, {} in flight, {} queued",
+            self.elapsed.as_secs(),
+            self.crawled_instances,
+            self.failed_instances,
+            self.running_jobs,
+            self.queued_jobs
+        )
+    }
+}
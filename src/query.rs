@@ -0,0 +1,285 @@
+use crate::crawl::CrawlResult;
+use crate::output::InstanceRow;
+use anyhow::{anyhow, Error};
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+/// Filter expression over the fields of `InstanceRow`, eg
+/// `users_active_month > 100 && registration_mode == "Open"`. Supports `==`, `!=`, `<`, `<=`,
+/// `>`, `>=`, `&&`, `||`, `!` and parentheses, with numbers, strings and booleans as values.
+#[derive(Debug, Clone)]
+pub struct Query {
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Compare(String, CompareOp, Value),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Value),
+    Op(CompareOp),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl FromStr for Query {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = tokenize(s)?.into_iter().peekable();
+        let expr = parse_or(&mut tokens)?;
+        if let Some(token) = tokens.next() {
+            return Err(anyhow!("unexpected {token:?} in query"));
+        }
+        Ok(Query { expr })
+    }
+}
+
+impl Query {
+    /// Instances which match the query, as rows with the selected fields, or all fields if none
+    /// are selected
+    pub fn run(&self, results: &[CrawlResult], fields: &[String]) -> Result<Vec<Value>, Error> {
+        let mut rows = vec![];
+        for result in results {
+            let Value::Object(row) = serde_json::to_value(InstanceRow::new(result))? else {
+                return Err(anyhow!("instance row is not an object"));
+            };
+            if !self.expr.eval(&row)? {
+                continue;
+            }
+            if fields.is_empty() {
+                rows.push(Value::Object(row));
+                continue;
+            }
+            let mut selected = Map::new();
+            for field in fields {
+                let value = row
+                    .get(field)
+                    .ok_or_else(|| anyhow!("unknown field {field}"))?;
+                selected.insert(field.clone(), value.clone());
+            }
+            rows.push(Value::Object(selected));
+        }
+        Ok(rows)
+    }
+}
+
+impl Expr {
+    fn eval(&self, row: &Map<String, Value>) -> Result<bool, Error> {
+        Ok(match self {
+            Expr::Compare(field, op, expected) => {
+                let value = row
+                    .get(field)
+                    .ok_or_else(|| anyhow!("unknown field {field}"))?;
+                let ordering = match (value, expected) {
+                    (Value::Number(a), Value::Number(b)) => {
+                        let (a, b) = (a.as_f64(), b.as_f64());
+                        a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+                    }
+                    (Value::String(a), Value::String(b)) => a.cmp(b),
+                    (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+                    _ => return Err(anyhow!("cannot compare {field} with {expected}")),
+                };
+                match op {
+                    CompareOp::Eq => ordering == Ordering::Equal,
+                    CompareOp::Ne => ordering != Ordering::Equal,
+                    CompareOp::Lt => ordering == Ordering::Less,
+                    CompareOp::Le => ordering != Ordering::Greater,
+                    CompareOp::Gt => ordering == Ordering::Greater,
+                    CompareOp::Ge => ordering != Ordering::Less,
+                }
+            }
+            Expr::Not(expr) => !expr.eval(row)?,
+            Expr::And(a, b) => a.eval(row)? && b.eval(row)?,
+            Expr::Or(a, b) => a.eval(row)? || b.eval(row)?,
+        })
+    }
+}
+
+type Tokens = Peekable<std::vec::IntoIter<Token>>;
+
+fn parse_or(tokens: &mut Tokens) -> Result<Expr, Error> {
+    let mut expr = parse_and(tokens)?;
+    while tokens.next_if_eq(&Token::Or).is_some() {
+        expr = Expr::Or(Box::new(expr), Box::new(parse_and(tokens)?));
+    }
+    Ok(expr)
+}
+
+fn parse_and(tokens: &mut Tokens) -> Result<Expr, Error> {
+    let mut expr = parse_unary(tokens)?;
+    while tokens.next_if_eq(&Token::And).is_some() {
+        expr = Expr::And(Box::new(expr), Box::new(parse_unary(tokens)?));
+    }
+    Ok(expr)
+}
+
+fn parse_unary(tokens: &mut Tokens) -> Result<Expr, Error> {
+    match tokens.next() {
+        Some(Token::Not) => Ok(Expr::Not(Box::new(parse_unary(tokens)?))),
+        Some(Token::Open) => {
+            let expr = parse_or(tokens)?;
+            match tokens.next() {
+                Some(Token::Close) => Ok(expr),
+                _ => Err(anyhow!("missing ) in query")),
+            }
+        }
+        Some(Token::Ident(field)) => match (tokens.next(), tokens.next()) {
+            (Some(Token::Op(op)), Some(Token::Literal(value))) => {
+                Ok(Expr::Compare(field, op, value))
+            }
+            _ => Err(anyhow!(
+                "expected comparison like {field} == value in query"
+            )),
+        },
+        Some(token) => Err(anyhow!("unexpected {token:?} in query")),
+        None => Err(anyhow!("unexpected end of query")),
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            '=' if chars.next_if_eq(&'=').is_some() => Token::Op(CompareOp::Eq),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Op(CompareOp::Ne),
+            '!' => Token::Not,
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Op(CompareOp::Le),
+            '<' => Token::Op(CompareOp::Lt),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Op(CompareOp::Ge),
+            '>' => Token::Op(CompareOp::Gt),
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => string.push(c),
+                        None => return Err(anyhow!("missing closing \" in query")),
+                    }
+                }
+                Token::Literal(Value::String(string))
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let number = take_word(c, &mut chars);
+                let number: f64 = number
+                    .parse()
+                    .map_err(|_| anyhow!("invalid number {number} in query"))?;
+                Token::Literal(number.into())
+            }
+            c if c.is_alphabetic() || c == '_' => match take_word(c, &mut chars).as_str() {
+                "true" => Token::Literal(Value::Bool(true)),
+                "false" => Token::Literal(Value::Bool(false)),
+                ident => Token::Ident(ident.to_string()),
+            },
+            c => return Err(anyhow!("unexpected character {c} in query")),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn take_word(first: char, chars: &mut Peekable<Chars>) -> String {
+    let mut word = first.to_string();
+    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || matches!(c, '_' | '.')) {
+        word.push(c);
+    }
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawl::tests::result_json;
+    use serde_json::json;
+
+    fn matches(query: &str) -> Result<bool, Error> {
+        let row = json!({"a": 1, "b": 0, "c": 0, "name": "Example", "open": true});
+        let Value::Object(row) = row else {
+            unreachable!()
+        };
+        Query::from_str(query)?.expr.eval(&row)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert!(matches("a == 1 || b == 1 && c == 1").unwrap());
+        assert!(!matches("(a == 1 || b == 1) && c == 1").unwrap());
+        assert!(matches("b == 1 && c == 1 || a == 1").unwrap());
+    }
+
+    #[test]
+    fn not_applies_to_the_next_comparison() {
+        assert!(!matches("!a == 1").unwrap());
+        assert!(matches("!a == 1 || open == true").unwrap());
+        assert!(matches("!(a == 1 && b == 1)").unwrap());
+        assert!(matches("!!(a >= 1)").unwrap());
+    }
+
+    #[test]
+    fn compares_numbers_strings_and_booleans() {
+        assert!(matches("a > 0.5 && a <= 1 && b != 1 && c < 1").unwrap());
+        assert!(matches(r#"name == "Example" && name > "A""#).unwrap());
+        assert!(matches("open == true && open != false").unwrap());
+    }
+
+    #[test]
+    fn type_mismatch_is_an_error() {
+        assert!(matches(r#"a == "1""#).is_err());
+        assert!(matches("name == 1").is_err());
+        assert!(matches("open == 1").is_err());
+    }
+
+    #[test]
+    fn unknown_field_is_an_error() {
+        assert!(matches("missing == 1").is_err());
+    }
+
+    #[test]
+    fn invalid_queries_are_rejected() {
+        assert!(Query::from_str(r#"name == "Example"#).is_err());
+        assert!(Query::from_str("(a == 1").is_err());
+        assert!(Query::from_str("a == 1)").is_err());
+        assert!(Query::from_str("a == 1 &&").is_err());
+        assert!(Query::from_str("a = 1").is_err());
+        assert!(Query::from_str("a == 1x").is_err());
+    }
+
+    #[test]
+    fn run_selects_fields_of_matching_instances() -> Result<(), Error> {
+        let results = vec![serde_json::from_value(result_json())?];
+        let query = Query::from_str(r#"registration_mode == "Open""#)?;
+        let rows = query.run(&results, &["domain".to_string()])?;
+        assert_eq!(rows, [json!({"domain": "example.com"})]);
+        let query = Query::from_str(r#"registration_mode == "Closed""#)?;
+        assert!(query.run(&results, &[])?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+use crate::checkpoint::PendingJob;
+use crate::crawl::CrawlJob;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// At most one in this many workers may crawl subdomains of the same apex domain at a time
+const MAX_APEX_SHARE: u32 = 10;
+
+/// Queue of pending crawl jobs. Jobs with higher priority are handed out first, jobs with equal
+/// priority in the order they were added. Each domain is queued only once, unless it is found
+/// again at a lower distance. Jobs of an apex domain which already has many running jobs are
+/// held back until one of them finishes, so that many subdomains of one apex don't occupy all
+/// workers.
+pub struct JobQueue {
+    jobs: Mutex<QueuedJobs>,
+    running: Mutex<RunningJobs>,
+    sequence: AtomicU64,
+    notify: Notify,
+    /// Maximum number of queued jobs before `push_or_wait` waits, unbounded if `None`
+    capacity: Option<usize>,
+    workers: u32,
+    /// Number of workers waiting in `push_or_wait`
+    waiting: AtomicU32,
+    space: Notify,
+    /// Set once the crawl is over, so that waiting workers stop
+    closed: AtomicBool,
+}
+
+#[derive(Default)]
+struct QueuedJobs {
+    heap: BinaryHeap<QueuedJob>,
+    /// Jobs which are held back by apex domain, because too many of the apex are running
+    held: HashMap<String, BinaryHeap<QueuedJob>>,
+    /// Lowest distance at which each domain is queued
+    distances: HashMap<String, u8>,
+}
+
+/// Jobs which were handed out and are not finished yet
+#[derive(Default)]
+struct RunningJobs {
+    by_domain: HashMap<String, RunningDomain>,
+    /// Number of running jobs of each apex domain
+    per_apex: HashMap<String, usize>,
+}
+
+/// A domain can run twice at the same time, if it is found again at a lower distance while it is
+/// running
+struct RunningDomain {
+    /// The job with the lowest distance
+    job: PendingJob,
+    count: usize,
+}
+
+impl JobQueue {
+    pub fn new(capacity: Option<usize>, workers: u32) -> Self {
+        JobQueue {
+            jobs: Mutex::new(QueuedJobs::default()),
+            running: Mutex::new(RunningJobs::default()),
+            sequence: AtomicU64::new(0),
+            notify: Notify::new(),
+            capacity,
+            workers,
+            waiting: AtomicU32::new(0),
+            space: Notify::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Add a job, regardless of capacity
+    pub fn push(&self, job: CrawlJob) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.contains(&job) {
+            return;
+        }
+        jobs.distances
+            .insert(job.domain.clone(), job.current_distance);
+        let sequence = self.sequence.fetch_add(1, AtomicOrdering::Relaxed);
+        jobs.heap.push(QueuedJob { sequence, job });
+        drop(jobs);
+        self.notify.notify_one();
+    }
+
+    /// Add a job, waiting while the queue is full. Doesn't wait if all other workers are waiting
+    /// as well, because then nobody would take jobs out of the queue anymore.
+    pub async fn push_or_wait(&self, job: CrawlJob) {
+        if let Some(capacity) = self.capacity {
+            loop {
+                let space = self.space.notified();
+                {
+                    let jobs = self.jobs.lock().unwrap();
+                    if jobs.len() < capacity || jobs.contains(&job) {
+                        break;
+                    }
+                }
+                if self.waiting.fetch_add(1, AtomicOrdering::SeqCst) + 1 >= self.workers {
+                    self.waiting.fetch_sub(1, AtomicOrdering::SeqCst);
+                    break;
+                }
+                space.await;
+                self.waiting.fetch_sub(1, AtomicOrdering::SeqCst);
+            }
+        }
+        self.push(job);
+    }
+
+    pub fn finish(&self, domain: &str) {
+        // locked in the same order as in `pop`
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut running = self.running.lock().unwrap();
+        if running.finish(domain) {
+            // A job of the same apex may be waiting for this one
+            jobs.release(apex_domain(domain));
+        }
+        drop((jobs, running));
+        self.notify.notify_one();
+    }
+
+    /// Number of queued and running jobs
+    pub fn counts(&self) -> (usize, usize) {
+        let queued = self.jobs.lock().unwrap().len();
+        let running = self.running.lock().unwrap();
+        (queued, running.by_domain.values().map(|r| r.count).sum())
+    }
+
+    /// Queued and running jobs
+    pub fn snapshot(&self) -> Vec<PendingJob> {
+        let jobs = self.jobs.lock().unwrap();
+        let running = self.running.lock().unwrap();
+        let queued = jobs.heap.iter().chain(jobs.held.values().flatten());
+        running
+            .by_domain
+            .values()
+            .map(|r| r.job.clone())
+            .chain(queued.map(|q| PendingJob::from(&q.job)))
+            .collect()
+    }
+
+    /// Drop all pending jobs
+    pub fn clear(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.heap.clear();
+        jobs.held.clear();
+        jobs.distances.clear();
+    }
+
+    /// Stop handing out jobs, `pop` returns `None` from now on
+    pub fn close(&self) {
+        self.closed.store(true, AtomicOrdering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// Wait until a job is available and return it, or `None` once the queue is closed. Call
+    /// `finish` once the job is done.
+    pub async fn pop(&self) -> Option<CrawlJob> {
+        loop {
+            // created before checking, so that a concurrent `close` can't be missed
+            let notified = self.notify.notified();
+            if self.closed.load(AtomicOrdering::SeqCst) {
+                return None;
+            }
+            // keep the queue locked until the job is marked as running, so that it is always
+            // included in snapshots
+            let job = {
+                let mut jobs = self.jobs.lock().unwrap();
+                let mut running = self.running.lock().unwrap();
+                let max_per_apex = (self.workers / MAX_APEX_SHARE).max(1) as usize;
+                let job = jobs.pop(&running, max_per_apex);
+                if let Some(job) = &job {
+                    running.start(job);
+                }
+                job
+            };
+            if let Some(job) = job {
+                self.space.notify_one();
+                return Some(job);
+            }
+            notified.await;
+        }
+    }
+}
+
+impl QueuedJobs {
+    /// Whether the domain is queued already, at the same or lower distance
+    fn contains(&self, job: &CrawlJob) -> bool {
+        self.distances
+            .get(&job.domain)
+            .map_or(false, |d| *d <= job.current_distance)
+    }
+
+    /// Number of queued jobs, including held ones
+    fn len(&self) -> usize {
+        self.heap.len() + self.held.values().map(BinaryHeap::len).sum::<usize>()
+    }
+
+    /// Take the first job whose apex domain has less than the maximum of running jobs. Jobs of
+    /// other apex domains are held until `release` is called for their apex. Jobs of a domain
+    /// which is already running at the same or lower distance are dropped.
+    fn pop(&mut self, running: &RunningJobs, max_per_apex: usize) -> Option<CrawlJob> {
+        loop {
+            let queued = self.heap.pop()?;
+            let job = &queued.job;
+            let apex = apex_domain(&job.domain);
+            if running.covers(job) {
+                self.remove_distance(job);
+            } else if running.per_apex.get(apex).copied().unwrap_or_default() < max_per_apex {
+                self.remove_distance(job);
+                return Some(queued.job);
+            } else {
+                self.held.entry(apex.to_string()).or_default().push(queued);
+            }
+        }
+    }
+
+    /// Forget the distance of a job which is taken out of the queue, unless the domain is queued
+    /// again at a lower distance
+    fn remove_distance(&mut self, job: &CrawlJob) {
+        if self.distances.get(&job.domain) == Some(&job.current_distance) {
+            self.distances.remove(&job.domain);
+        }
+    }
+
+    /// Queue the first held job of the apex domain again, once one of its jobs finished
+    fn release(&mut self, apex: &str) {
+        let Some(held) = self.held.get_mut(apex) else {
+            return;
+        };
+        if let Some(queued) = held.pop() {
+            self.heap.push(queued);
+        }
+        if held.is_empty() {
+            self.held.remove(apex);
+        }
+    }
+}
+
+impl RunningJobs {
+    /// Whether the domain of the job is running at the same or lower distance
+    fn covers(&self, job: &CrawlJob) -> bool {
+        self.by_domain
+            .get(&job.domain)
+            .map_or(false, |r| r.job.distance <= job.current_distance)
+    }
+
+    fn start(&mut self, job: &CrawlJob) {
+        *self
+            .per_apex
+            .entry(apex_domain(&job.domain).to_string())
+            .or_default() += 1;
+        let pending = PendingJob::from(job);
+        self.by_domain
+            .entry(job.domain.clone())
+            .and_modify(|r| {
+                r.count += 1;
+                r.job = pending.clone();
+            })
+            .or_insert(RunningDomain {
+                job: pending,
+                count: 1,
+            });
+    }
+
+    /// Count one job of the domain as finished, returns false if none was running
+    fn finish(&mut self, domain: &str) -> bool {
+        let Some(running) = self.by_domain.get_mut(domain) else {
+            return false;
+        };
+        running.count -= 1;
+        if running.count == 0 {
+            self.by_domain.remove(domain);
+        }
+        let apex = apex_domain(domain);
+        if let Some(count) = self.per_apex.get_mut(apex) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_apex.remove(apex);
+            }
+        }
+        true
+    }
+}
+
+/// Last two labels of the domain. Without the public suffix list this treats eg `co.uk` as an
+/// apex, which only makes fairness stricter for those domains.
+fn apex_domain(domain: &str) -> &str {
+    let mut dots = domain.rmatch_indices('.').map(|(i, _)| i);
+    dots.next();
+    match dots.next() {
+        Some(i) => &domain[i + 1..],
+        None => domain,
+    }
+}
+
+struct QueuedJob {
+    sequence: u64,
+    job: CrawlJob,
+}
+
+impl Ord for QueuedJob {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.job
+            .priority
+            .cmp(&other.job.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for QueuedJob {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedJob {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crawl::tests::job;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    async fn pop(queue: &JobQueue) -> Option<(String, u8)> {
+        let job = timeout(Duration::from_millis(100), queue.pop())
+            .await
+            .ok()??;
+        Some((job.domain, job.current_distance))
+    }
+
+    #[tokio::test]
+    async fn domain_found_at_lower_distance_releases_its_apex() {
+        // two running jobs per apex
+        let queue = JobQueue::new(None, 20);
+        queue.push(job("a.example.com", 2));
+        queue.push(job("a.example.com", 1));
+        queue.push(job("b.example.com", 1));
+        queue.push(job("c.example.com", 1));
+        assert_eq!(pop(&queue).await, Some(("a.example.com".to_string(), 2)));
+        assert_eq!(pop(&queue).await, Some(("a.example.com".to_string(), 1)));
+        assert_eq!(pop(&queue).await, None);
+        assert_eq!(queue.counts(), (2, 2));
+
+        queue.finish("a.example.com");
+        assert_eq!(pop(&queue).await, Some(("b.example.com".to_string(), 1)));
+        queue.finish("a.example.com");
+        assert_eq!(pop(&queue).await, Some(("c.example.com".to_string(), 1)));
+        queue.finish("b.example.com");
+        queue.finish("c.example.com");
+        assert_eq!(queue.counts(), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn domain_running_at_same_distance_is_dropped() {
+        let queue = JobQueue::new(None, 20);
+        queue.push(job("example.com", 1));
+        assert!(pop(&queue).await.is_some());
+        queue.push(job("example.com", 1));
+        assert_eq!(pop(&queue).await, None);
+        queue.finish("example.com");
+        assert_eq!(queue.counts(), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn close_stops_waiting_workers() {
+        let queue = Arc::new(JobQueue::new(None, 2));
+        let workers: Vec<_> = (0..2)
+            .map(|_| {
+                let queue = queue.clone();
+                tokio::spawn(async move { queue.pop().await.is_none() })
+            })
+            .collect();
+        tokio::task::yield_now().await;
+        queue.close();
+        for worker in workers {
+            let stopped = timeout(Duration::from_secs(1), worker).await;
+            assert!(stopped.unwrap().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn pop_after_close_returns_none() {
+        let queue = JobQueue::new(None, 1);
+        queue.close();
+        assert!(queue.pop().await.is_none());
+    }
+
+    #[test]
+    fn apex_domain_is_last_two_labels() {
+        assert_eq!(apex_domain("a.b.example.com"), "example.com");
+        assert_eq!(apex_domain("example.com"), "example.com");
+        assert_eq!(apex_domain("localhost"), "localhost");
+    }
+}
@@ -0,0 +1,52 @@
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use task_local_extensions::Extensions;
+use tokio::time::{sleep_until, Instant};
+
+/// Spaces out all requests evenly, so that no more than the given number of requests per second
+/// are sent in total, regardless of the number of crawl jobs
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    interval: Duration,
+    /// Earliest time at which the next request may be sent
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: f64) -> Self {
+        RateLimiter {
+            interval: Duration::from_secs_f64(1.0 / requests_per_second),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until the next request may be sent
+    pub async fn wait(&self) {
+        let slot = {
+            let mut next_slot = self.next.lock().unwrap();
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval;
+            slot
+        };
+        sleep_until(slot).await;
+    }
+}
+
+/// Applies the rate limiter to all requests of a client. The limiter can be shared with other
+/// connections to instances.
+pub struct RateLimitMiddleware(pub(crate) Arc<RateLimiter>);
+
+#[async_trait::async_trait]
+impl Middleware for RateLimitMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        self.0.wait().await;
+        next.run(req, extensions).await
+    }
+}
@@ -0,0 +1,137 @@
+use crate::crawl::CrawlResult;
+use crate::directory::InstanceDirectoryEntry;
+use lemmy_api_common_v019::lemmy_db_schema::RegistrationMode;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// Instances with fewer monthly active users are small
+const SMALL_INSTANCE_USERS: i64 = 100;
+/// Instances with more monthly active users are large
+const LARGE_INSTANCE_USERS: i64 = 1000;
+/// Number of instances which are recommended on join-lemmy.org, before language quotas
+const RECOMMENDED_INSTANCES: usize = 30;
+/// Minimum number of recommended instances for each of `MAJOR_LANGUAGES`, if there are enough
+/// matching instances
+const RECOMMENDED_PER_LANGUAGE: usize = 3;
+/// Languages which are guaranteed some recommendations, as the most active instances are mostly
+/// English
+pub const MAJOR_LANGUAGES: [&str; 12] = [
+    "en", "de", "fr", "es", "pt", "it", "nl", "pl", "ru", "ja", "zh", "ko",
+];
+
+/// What a new user is looking for in an instance. The default accepts only instances with open
+/// signups and without NSFW content, of any language and size.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct InstancePreferences {
+    /// Language code like `en`, which the instance must allow for discussions
+    pub language: Option<String>,
+    /// Instances of other sizes are ranked lower, but not left out
+    pub size: Option<InstanceSize>,
+    /// Include instances which require an application to sign up
+    pub allow_applications: bool,
+    /// Include instances which allow NSFW content
+    pub allow_nsfw: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstanceSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl InstanceSize {
+    fn of(users_active_month: i64) -> Self {
+        if users_active_month < SMALL_INSTANCE_USERS {
+            InstanceSize::Small
+        } else if users_active_month > LARGE_INSTANCE_USERS {
+            InstanceSize::Large
+        } else {
+            InstanceSize::Medium
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct InstanceRecommendation {
+    #[serde(flatten)]
+    pub directory: InstanceDirectoryEntry,
+    pub users_active_month: i64,
+    /// Higher is better, only meaningful in comparison to other recommendations
+    pub score: f64,
+}
+
+/// Instances which match the preferences, best first. Activity counts logarithmically, so that
+/// new users are spread out instead of all joining the largest instance.
+pub fn recommend_instances(
+    results: &[CrawlResult],
+    preferences: &InstancePreferences,
+) -> Vec<InstanceRecommendation> {
+    let mut recommendations: Vec<_> = results
+        .iter()
+        .filter(|r| match r.site_info.registration_mode() {
+            RegistrationMode::Open => true,
+            RegistrationMode::RequireApplication => preferences.allow_applications,
+            RegistrationMode::Closed => false,
+        })
+        .filter(|r| preferences.allow_nsfw || !r.site_info.nsfw_enabled())
+        .filter(|r| match &preferences.language {
+            Some(language) => r.site_info.discussion_languages().contains(language),
+            None => true,
+        })
+        .map(|r| {
+            let users_active_month = r.site_info.users_active_month();
+            let mut score = (1.0 + users_active_month.max(0) as f64).ln();
+            if preferences
+                .size
+                .map_or(false, |s| s != InstanceSize::of(users_active_month))
+            {
+                score /= 2.0;
+            }
+            // Likely to be unreliable for new users as well
+            if r.slow_response {
+                score /= 2.0;
+            }
+            InstanceRecommendation {
+                directory: InstanceDirectoryEntry::new(r),
+                users_active_month,
+                score,
+            }
+        })
+        .collect();
+    recommendations.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+    recommendations
+}
+
+/// Domains which join-lemmy.org should recommend with default preferences. These are the best
+/// ranked instances overall, plus the best ranked instances for each major language which
+/// doesn't have enough recommendations yet.
+pub fn recommended_domains(results: &[CrawlResult]) -> HashSet<String> {
+    let mut recommended: HashSet<_> = recommend_instances(results, &InstancePreferences::default())
+        .into_iter()
+        .take(RECOMMENDED_INSTANCES)
+        .map(|r| r.directory.domain)
+        .collect();
+    for language in MAJOR_LANGUAGES {
+        let preferences = InstancePreferences {
+            language: Some(language.to_string()),
+            ..Default::default()
+        };
+        let ranked: Vec<_> = recommend_instances(results, &preferences)
+            .into_iter()
+            .map(|r| r.directory.domain)
+            .collect();
+        let missing = RECOMMENDED_PER_LANGUAGE
+            .saturating_sub(ranked.iter().filter(|d| recommended.contains(*d)).count());
+        let extra: Vec<_> = ranked
+            .into_iter()
+            .filter(|d| !recommended.contains(d))
+            .take(missing)
+            .collect();
+        recommended.extend(extra);
+    }
+    recommended
+}
@@ -0,0 +1,190 @@
+use crate::aggregate::{
+    instance_text_data, joinlemmy_instance_data, minimal_instance_data, normalized_instance_data,
+    TotalStats,
+};
+use crate::crawl::CrawlResult;
+use anyhow::Error;
+use serde_json::Value;
+
+/// Named sets of data which are published from a crawl. All outputs go through this, so that
+/// fields which should not be published are removed in one place.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RedactionProfile {
+    /// Everything, including contact hints and request timings
+    #[default]
+    Full,
+    /// Everything except contact hints and request timings, which are only meant for the
+    /// operator of the crawler
+    Public,
+    /// Totals with domain, version and user counts of each instance
+    Minimal,
+    /// Instance list with the fields needed by join-lemmy.org
+    Joinlemmy,
+    /// Totals with instance fields mapped to the same names for all API versions, plus the raw
+    /// site response
+    Normalized,
+    /// Only domain, name, description and sidebar of each instance
+    Text,
+}
+
+impl RedactionProfile {
+    /// JSON with the data of this profile
+    pub fn apply(self, stats: &TotalStats) -> Result<Value, Error> {
+        let value = match self {
+            RedactionProfile::Full => serde_json::to_value(stats)?,
+            RedactionProfile::Public => {
+                let mut stats = stats.clone();
+                self.redact(&mut stats);
+                serde_json::to_value(stats)?
+            }
+            RedactionProfile::Minimal => serde_json::to_value(minimal_instance_data(stats))?,
+            RedactionProfile::Joinlemmy => serde_json::to_value(joinlemmy_instance_data(stats))?,
+            RedactionProfile::Normalized => serde_json::to_value(normalized_instance_data(stats))?,
+            RedactionProfile::Text => serde_json::to_value(instance_text_data(stats))?,
+        };
+        Ok(value)
+    }
+
+    /// Remove the private fields of all instances in place, for outputs which are built from the
+    /// crawl results instead of the JSON of `apply`, eg the server, instance pages or graph
+    pub fn redact(self, stats: &mut TotalStats) {
+        for instance in &mut stats.instance_details {
+            self.redact_instance(instance);
+        }
+    }
+
+    /// Only `Full` keeps contact hints and request timings
+    pub fn redact_instance(self, instance: &mut CrawlResult) {
+        if self != RedactionProfile::Full {
+            instance.contact = None;
+            instance.diagnostics = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::aggregate;
+    use crate::crawl::tests::result_json;
+    use crate::CrawlOutput;
+    use serde_json::json;
+
+    fn stats() -> TotalStats {
+        let mut json = result_json();
+        json["contact"] = json!({"admins": [], "matrix_ids": ["@admin:example.com"], "emails": []});
+        json["diagnostics"] = json!({"dns_lookup_ms": 1, "endpoints": []});
+        aggregate(CrawlOutput {
+            results: vec![serde_json::from_value(json).unwrap()],
+            ..Default::default()
+        })
+    }
+
+    /// Fields of the first instance in the output of the profile
+    fn instance_fields(profile: RedactionProfile, instances: &str) -> Vec<String> {
+        let value = profile.apply(&stats()).unwrap();
+        let instances = match instances {
+            "" => &value,
+            field => &value[field],
+        };
+        let mut fields: Vec<_> = instances[0].as_object().unwrap().keys().cloned().collect();
+        fields.sort();
+        fields
+    }
+
+    #[test]
+    fn full_keeps_private_fields() {
+        let value = RedactionProfile::Full.apply(&stats()).unwrap();
+        let instance = &value["instance_details"][0];
+        assert_eq!(instance["contact"]["matrix_ids"][0], "@admin:example.com");
+        assert_eq!(instance["diagnostics"]["dns_lookup_ms"], 1);
+    }
+
+    #[test]
+    fn public_removes_only_contact_and_diagnostics() {
+        let stats = stats();
+        let mut full = RedactionProfile::Full.apply(&stats).unwrap();
+        let public = RedactionProfile::Public.apply(&stats).unwrap();
+        full["instance_details"][0]["contact"] = Value::Null;
+        full["instance_details"][0]["diagnostics"] = Value::Null;
+        assert_eq!(public, full);
+    }
+
+    #[test]
+    fn redact_removes_private_fields() {
+        let mut stats = stats();
+        for profile in [RedactionProfile::Public, RedactionProfile::Text] {
+            let mut redacted = stats.clone();
+            profile.redact(&mut redacted);
+            assert!(redacted.instance_details[0].contact.is_none());
+            assert!(redacted.instance_details[0].diagnostics.is_none());
+        }
+        RedactionProfile::Full.redact(&mut stats);
+        assert!(stats.instance_details[0].contact.is_some());
+        assert!(stats.instance_details[0].diagnostics.is_some());
+    }
+
+    #[test]
+    fn minimal_has_only_counts() {
+        assert_eq!(
+            instance_fields(RedactionProfile::Minimal, "instances"),
+            ["domain", "total_users", "users_active_month", "version"]
+        );
+    }
+
+    #[test]
+    fn joinlemmy_has_directory_fields() {
+        assert_eq!(
+            instance_fields(RedactionProfile::Joinlemmy, ""),
+            [
+                "comments",
+                "description",
+                "domain",
+                "icon",
+                "languages",
+                "name",
+                "posts",
+                "recommended",
+                "registration_mode",
+                "total_users",
+                "users_active_month",
+                "version",
+            ]
+        );
+    }
+
+    #[test]
+    fn normalized_has_no_private_fields() {
+        assert_eq!(
+            instance_fields(RedactionProfile::Normalized, "instances"),
+            [
+                "admins",
+                "api_version",
+                "comments",
+                "description",
+                "domain",
+                "icon",
+                "languages",
+                "name",
+                "nsfw_enabled",
+                "posts",
+                "raw",
+                "registration_mode",
+                "total_users",
+                "users_active_day",
+                "users_active_halfyear",
+                "users_active_month",
+                "users_active_week",
+                "version",
+            ]
+        );
+    }
+
+    #[test]
+    fn text_has_only_text_fields() {
+        assert_eq!(
+            instance_fields(RedactionProfile::Text, ""),
+            ["description", "domain", "name", "sidebar"]
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use reqwest::Response;
+use reqwest_retry::{DefaultRetryableStrategy, Retryable, RetryableStrategy};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Retries which are always allowed, so that failures early in a crawl can be retried
+const MIN_RETRIES: u64 = 10;
+
+/// Limits retries to a fraction of all requests in a crawl, so that a widespread outage leads to
+/// fast failures instead of multiplying the crawl time by the number of retries.
+#[derive(Debug)]
+pub struct RetryBudget {
+    ratio: f64,
+    requests: AtomicU64,
+    retries: AtomicU64,
+}
+
+impl RetryBudget {
+    pub fn new(ratio: f64) -> Self {
+        RetryBudget {
+            ratio,
+            requests: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+        }
+    }
+
+    /// Check if another retry is allowed and count it. Not exact with concurrent requests,
+    /// which is fine for this purpose.
+    fn try_retry(&self) -> bool {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let allowed = ((requests as f64 * self.ratio) as u64).max(MIN_RETRIES);
+        if self.retries.load(Ordering::Relaxed) < allowed {
+            self.retries.fetch_add(1, Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Same as the default strategy, but transient errors are treated as fatal once the retry budget
+/// is used up
+pub struct BudgetedRetryStrategy(pub Arc<RetryBudget>);
+
+impl RetryableStrategy for BudgetedRetryStrategy {
+    fn handle(&self, res: &Result<Response, reqwest_middleware::Error>) -> Option<Retryable> {
+        self.0.requests.fetch_add(1, Ordering::Relaxed);
+        match DefaultRetryableStrategy.handle(res) {
+            Some(Retryable::Transient) if !self.0.try_retry() => Some(Retryable::Fatal),
+            retryable => retryable,
+        }
+    }
+}
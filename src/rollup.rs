@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+
+/// Length of the periods which stored crawls are summarized into
+#[derive(Debug, Clone, Copy)]
+pub enum RollupPeriod {
+    /// ISO week, eg 2024-W05
+    Week,
+    /// Calendar month, eg 2024-02
+    Month,
+}
+
+impl FromStr for RollupPeriod {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "week" => Ok(RollupPeriod::Week),
+            "month" => Ok(RollupPeriod::Month),
+            _ => Err(anyhow!("unsupported period {s}, expected week or month")),
+        }
+    }
+}
+
+impl RollupPeriod {
+    fn key(self, time: DateTime<Utc>) -> String {
+        match self {
+            RollupPeriod::Week => time.format("%G-W%V").to_string(),
+            RollupPeriod::Month => time.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+/// Counts of the network or of a single instance from one stored crawl
+#[derive(Debug, Serialize)]
+pub struct Sample {
+    pub crawled_at: DateTime<Utc>,
+    /// `None` for network totals
+    pub domain: Option<String>,
+    pub total_users: i64,
+    pub users_active_month: i64,
+}
+
+impl Sample {
+    /// Set counts of an instance below the threshold to 0. Network totals are not affected.
+    pub fn suppress_small_counts(&mut self, threshold: i64) {
+        if self.domain.is_none() {
+            return;
+        }
+        for count in [&mut self.total_users, &mut self.users_active_month] {
+            if *count < threshold {
+                *count = 0;
+            }
+        }
+    }
+}
+
+/// Keep only the first crawl of each UTC day, so that days with more crawls don't count more when
+/// crawl times drift. Samples of one crawl are kept or dropped together.
+pub fn daily_snapshots(samples: Vec<Sample>) -> Vec<Sample> {
+    let mut first_of_day: BTreeMap<NaiveDate, DateTime<Utc>> = BTreeMap::new();
+    for s in &samples {
+        let first = first_of_day
+            .entry(s.crawled_at.date_naive())
+            .or_insert(s.crawled_at);
+        *first = (*first).min(s.crawled_at);
+    }
+    let snapshots: HashSet<_> = first_of_day.into_values().collect();
+    samples
+        .into_iter()
+        .filter(|s| snapshots.contains(&s.crawled_at))
+        .collect()
+}
+
+/// Summary of all crawls in a period, for the whole network or a single instance
+#[derive(Debug, Serialize)]
+pub struct RollupRow {
+    pub period: String,
+    /// Empty for network totals
+    pub domain: Option<String>,
+    /// Number of crawls in the period which included this instance
+    pub crawls: usize,
+    /// Value from the last crawl in the period
+    pub total_users_end: i64,
+    pub total_users_max: i64,
+    pub users_active_month_avg: f64,
+    pub users_active_month_max: i64,
+    pub users_active_month_end: i64,
+}
+
+/// Summarize samples per period, with network totals first in each period and instances sorted
+/// by domain
+pub fn rollup(mut samples: Vec<Sample>, period: RollupPeriod) -> Vec<RollupRow> {
+    samples.sort_by_key(|s| s.crawled_at);
+    let mut groups: BTreeMap<(String, Option<String>), Vec<Sample>> = BTreeMap::new();
+    for s in samples {
+        let key = (period.key(s.crawled_at), s.domain.clone());
+        groups.entry(key).or_default().push(s);
+    }
+    groups
+        .into_iter()
+        .map(|((period, domain), samples)| {
+            let last = samples.last().expect("groups are not empty");
+            let active_month_sum: i64 = samples.iter().map(|s| s.users_active_month).sum();
+            RollupRow {
+                period,
+                domain,
+                crawls: samples.len(),
+                total_users_end: last.total_users,
+                total_users_max: samples
+                    .iter()
+                    .map(|s| s.total_users)
+                    .max()
+                    .unwrap_or_default(),
+                users_active_month_avg: active_month_sum as f64 / samples.len() as f64,
+                users_active_month_max: samples
+                    .iter()
+                    .map(|s| s.users_active_month)
+                    .max()
+                    .unwrap_or_default(),
+                users_active_month_end: last.users_active_month,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,409 @@
+use crate::aggregate::{minimal_community_data, TotalStats};
+use crate::crawl::CrawlResult;
+use crate::grafana::{instance_rows, sample_rows};
+use crate::redact::RedactionProfile;
+use crate::storage::Store;
+use crate::Crawler;
+use anyhow::Error;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use lemmy_api_common_v019::lemmy_db_schema::RegistrationMode;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tracing::{debug, warn};
+
+/// Results of the most recent crawl, and the time when it finished
+#[derive(Debug, Default)]
+pub struct LatestCrawl {
+    latest: RwLock<Option<(DateTime<Utc>, Arc<TotalStats>)>>,
+    /// Instances which were crawled again since the latest crawl, and when
+    refreshed: RwLock<HashMap<String, DateTime<Utc>>>,
+    refresher: Option<InstanceRefresher>,
+    /// Stored crawls, for time series at `/grafana/history`
+    store: Option<Store>,
+    redaction: RedactionProfile,
+    suppress_counts_below: Option<i64>,
+}
+
+impl LatestCrawl {
+    /// Serve the network totals and instance counts saved in the store at `/grafana/history`
+    pub fn history(mut self, store: Store) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Crawl instances again when they are requested at `/instances/{domain}` and stale
+    pub fn refresh_stale(mut self, refresher: InstanceRefresher) -> Self {
+        self.refresher = Some(refresher);
+        self
+    }
+
+    /// Remove the private fields of this profile from all crawls and refreshed instances before
+    /// they are served. `Full` by default.
+    pub fn redaction(mut self, redaction: RedactionProfile) -> Self {
+        self.redaction = redaction;
+        self
+    }
+
+    /// Suppress small instance counts from the store at `/grafana/history`, like those of the
+    /// crawls which are passed to `update`
+    pub fn suppress_small_counts(mut self, threshold: Option<i64>) -> Self {
+        self.suppress_counts_below = threshold;
+        self
+    }
+
+    pub fn update(&self, mut stats: TotalStats) {
+        self.redaction.redact(&mut stats);
+        *self.latest.write().unwrap() = Some((Utc::now(), Arc::new(stats)));
+        self.refreshed.write().unwrap().clear();
+    }
+
+    fn get(&self) -> Option<(DateTime<Utc>, Arc<TotalStats>)> {
+        self.latest.read().unwrap().clone()
+    }
+
+    /// Crawl the instance again if its data is stale and the rate limit allows it. Only
+    /// instances from the latest crawl are refreshed, so that requests can't trigger crawls of
+    /// arbitrary domains.
+    async fn refresh_if_stale(&self, domain: &str) {
+        let (Some(refresher), Some((crawled_at, stats))) = (&self.refresher, self.get()) else {
+            return;
+        };
+        if !stats.instance_details.iter().any(|i| i.domain == domain) {
+            return;
+        }
+        let updated_at = self.refreshed.read().unwrap().get(domain).copied();
+        let age = Utc::now() - updated_at.unwrap_or(crawled_at);
+        if age.to_std().map_or(true, |age| age < refresher.stale_after) || !refresher.try_start() {
+            return;
+        }
+        match refresher.crawl(domain).await {
+            Ok(Some(result)) => self.replace_instance(result),
+            Ok(None) => debug!("Refresh of {domain} failed, keeping previous data"),
+            Err(e) => warn!("Refresh of {domain} failed: {e}"),
+        }
+    }
+
+    /// Totals are not updated, they change with the next full crawl
+    fn replace_instance(&self, mut result: CrawlResult) {
+        self.redaction.redact_instance(&mut result);
+        let mut latest = self.latest.write().unwrap();
+        let Some((_, stats)) = latest.as_mut() else {
+            return;
+        };
+        let mut updated = TotalStats::clone(stats);
+        if let Some(i) = updated
+            .instance_details
+            .iter_mut()
+            .find(|i| i.domain == result.domain)
+        {
+            // Depend on links from the other instances, so they are kept from the full crawl
+            result.linked_by_count = i.linked_by_count;
+            result.centrality = i.centrality.take();
+            self.refreshed
+                .write()
+                .unwrap()
+                .insert(result.domain.clone(), Utc::now());
+            *i = result;
+            *stats = Arc::new(updated);
+        }
+    }
+}
+
+/// Crawls single instances again between full crawls, at most one every `min_interval`
+#[derive(Debug)]
+pub struct InstanceRefresher {
+    crawler: Crawler,
+    /// Instances are refreshed once their data is older than this
+    stale_after: Duration,
+    min_interval: Duration,
+    last_refresh: Mutex<Option<Instant>>,
+    suppress_counts_below: Option<i64>,
+}
+
+impl InstanceRefresher {
+    pub fn new(crawler: Crawler, stale_after: Duration, min_interval: Duration) -> Self {
+        InstanceRefresher {
+            crawler,
+            stale_after,
+            min_interval,
+            last_refresh: Mutex::new(None),
+            suppress_counts_below: None,
+        }
+    }
+
+    /// Suppress small counts of refreshed instances like those of the full crawls, see
+    /// `TotalStats::suppress_small_counts`
+    pub fn suppress_small_counts(mut self, threshold: Option<i64>) -> Self {
+        self.suppress_counts_below = threshold;
+        self
+    }
+
+    /// Reserve the next refresh, false if the rate limit is reached
+    fn try_start(&self) -> bool {
+        let mut last_refresh = self.last_refresh.lock().unwrap();
+        if last_refresh.map_or(false, |last| last.elapsed() < self.min_interval) {
+            return false;
+        }
+        *last_refresh = Some(Instant::now());
+        true
+    }
+
+    /// `None` if the instance couldn't be crawled. Topics are classified by the crawl, if the
+    /// crawler is configured for it.
+    async fn crawl(&self, domain: &str) -> Result<Option<CrawlResult>, Error> {
+        let output = self
+            .crawler
+            .clone()
+            .start_instances(vec![domain.to_string()])
+            .max_distance(0)
+            .checkpoint(None)
+            .progress(None)
+            .run()
+            .await?;
+        let mut result = output.results.into_iter().find(|r| r.domain == domain);
+        if let (Some(result), Some(threshold)) = (&mut result, self.suppress_counts_below) {
+            result.suppress_small_counts(threshold);
+        }
+        Ok(result)
+    }
+}
+
+/// Header with the number of instances matching the filters, before pagination
+static TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+
+/// Query parameters for `/instances`. Without `limit` all matching instances are returned.
+#[derive(Debug, Deserialize)]
+struct InstanceQuery {
+    /// Starting at 1
+    page: Option<usize>,
+    limit: Option<usize>,
+    #[serde(default)]
+    sort: InstanceSort,
+    min_mau: Option<i64>,
+    /// Version prefix, eg `0.19` matches all 0.19.x versions
+    version: Option<String>,
+    registration_mode: Option<RegistrationMode>,
+    /// Code of an allowed discussion language, eg `en`
+    language: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum InstanceSort {
+    /// Monthly active users, descending
+    #[default]
+    Mau,
+    /// Total users, descending
+    Users,
+    Domain,
+}
+
+impl InstanceQuery {
+    fn matches(&self, i: &CrawlResult) -> bool {
+        let site_info = &i.site_info;
+        self.min_mau
+            .map_or(true, |min| site_info.users_active_month() >= min)
+            && self
+                .version
+                .as_ref()
+                .map_or(true, |v| site_info.version().starts_with(v.as_str()))
+            && self
+                .registration_mode
+                .map_or(true, |m| site_info.registration_mode() == m)
+            && self
+                .language
+                .as_ref()
+                .map_or(true, |l| site_info.discussion_languages().contains(l))
+    }
+
+    /// Filter, sort and paginate instances
+    fn apply<'a>(&self, instances: &'a [CrawlResult]) -> (usize, Vec<&'a CrawlResult>) {
+        let mut matching: Vec<_> = instances.iter().filter(|i| self.matches(i)).collect();
+        match self.sort {
+            InstanceSort::Mau => {
+                matching.sort_by_key(|i| Reverse(i.site_info.users_active_month()))
+            }
+            InstanceSort::Users => matching.sort_by_key(|i| Reverse(i.site_info.total_users())),
+            InstanceSort::Domain => matching.sort_by(|a, b| a.domain.cmp(&b.domain)),
+        }
+        let total = matching.len();
+        if let Some(limit) = self.limit {
+            // Saturating, as both come from the request
+            let offset = self
+                .page
+                .unwrap_or(1)
+                .saturating_sub(1)
+                .saturating_mul(limit);
+            matching = matching.into_iter().skip(offset).take(limit).collect();
+        }
+        (total, matching)
+    }
+}
+
+/// Query parameters for `/grafana/history`
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    /// Network totals if not given
+    domain: Option<String>,
+}
+
+/// Serve the latest crawl results as JSON at `/instances`, `/instances/{domain}`, `/communities`
+/// and `/totals`. Flat rows with timestamps for Grafana JSON datasources are served at
+/// `/grafana/instances` and, with a store, `/grafana/history`.
+pub async fn serve_results(addr: SocketAddr, latest: Arc<LatestCrawl>) -> Result<(), Error> {
+    let app = Router::new()
+        .route("/instances", get(instances))
+        .route("/instances/:domain", get(instance))
+        .route("/communities", get(communities))
+        .route("/totals", get(totals))
+        .route("/grafana/instances", get(grafana_instances))
+        .route("/grafana/history", get(grafana_history))
+        .with_state(latest);
+    let listener = TcpListener::bind(addr).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn instances(
+    State(latest): State<Arc<LatestCrawl>>,
+    Query(query): Query<InstanceQuery>,
+) -> Response {
+    respond(&latest, |stats| {
+        let (total, instances) = query.apply(&stats.instance_details);
+        let mut response = Json(instances).into_response();
+        response
+            .headers_mut()
+            .insert(TOTAL_COUNT.clone(), HeaderValue::from(total));
+        response
+    })
+}
+
+async fn instance(State(latest): State<Arc<LatestCrawl>>, Path(domain): Path<String>) -> Response {
+    latest.refresh_if_stale(&domain).await;
+    respond(&latest, |stats| {
+        match stats.instance_details.iter().find(|i| i.domain == domain) {
+            Some(instance) => Json(instance).into_response(),
+            None => (StatusCode::NOT_FOUND, "Instance not found").into_response(),
+        }
+    })
+}
+
+async fn communities(State(latest): State<Arc<LatestCrawl>>) -> Response {
+    respond(&latest, |stats| {
+        Json(minimal_community_data(stats)).into_response()
+    })
+}
+
+async fn totals(State(latest): State<Arc<LatestCrawl>>) -> Response {
+    respond(&latest, |stats| Json(stats.totals()).into_response())
+}
+
+async fn grafana_instances(State(latest): State<Arc<LatestCrawl>>) -> Response {
+    let Some((crawled_at, stats)) = latest.get() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "No crawl completed yet").into_response();
+    };
+    Json(instance_rows(&stats, crawled_at)).into_response()
+}
+
+async fn grafana_history(
+    State(latest): State<Arc<LatestCrawl>>,
+    Query(query): Query<HistoryQuery>,
+) -> Response {
+    let Some(store) = latest.store.clone() else {
+        return (StatusCode::NOT_FOUND, "History requires --store").into_response();
+    };
+    match tokio::task::spawn_blocking(move || store.samples()).await {
+        Ok(Ok(mut samples)) => {
+            if let Some(threshold) = latest.suppress_counts_below {
+                for sample in &mut samples {
+                    sample.suppress_small_counts(threshold);
+                }
+            }
+            Json(sample_rows(samples, query.domain.as_deref())).into_response()
+        }
+        Ok(Err(e)) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Build the response from latest crawl, with `Last-Modified` set to the time it finished
+fn respond(latest: &LatestCrawl, build: impl FnOnce(&TotalStats) -> Response) -> Response {
+    let Some((crawled_at, stats)) = latest.get() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "No crawl completed yet").into_response();
+    };
+    let mut response = build(&stats);
+    let last_modified = crawled_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    if let Ok(last_modified) = HeaderValue::from_str(&last_modified) {
+        response
+            .headers_mut()
+            .insert(header::LAST_MODIFIED, last_modified);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::aggregate;
+    use crate::crawl::tests::result_json;
+    use crate::CrawlOutput;
+
+    #[test]
+    fn refreshed_instance_keeps_links_from_full_crawl() {
+        let mut result: CrawlResult = serde_json::from_value(result_json()).unwrap();
+        result.linked_by_count = 5;
+        let latest = LatestCrawl::default();
+        latest.update(aggregate(CrawlOutput {
+            results: vec![result.clone()],
+            ..Default::default()
+        }));
+        result.linked_by_count = 0;
+        result.http_version = "HTTP/3.0".to_string();
+        latest.replace_instance(result);
+        let (_, stats) = latest.get().unwrap();
+        assert_eq!(stats.instance_details[0].linked_by_count, 5);
+        assert_eq!(stats.instance_details[0].http_version, "HTTP/3.0");
+        assert!(latest.refreshed.read().unwrap().contains_key("example.com"));
+    }
+
+    fn page(page: usize, limit: usize) -> InstanceQuery {
+        InstanceQuery {
+            page: Some(page),
+            limit: Some(limit),
+            sort: InstanceSort::Domain,
+            min_mau: None,
+            version: None,
+            registration_mode: None,
+            language: None,
+        }
+    }
+
+    #[test]
+    fn pages_past_the_end_are_empty() {
+        let instances: Vec<CrawlResult> = ["a.example", "b.example", "c.example"]
+            .iter()
+            .map(|domain| {
+                let mut result: CrawlResult = serde_json::from_value(result_json()).unwrap();
+                result.domain = domain.to_string();
+                result
+            })
+            .collect();
+        let (total, matching) = page(2, 2).apply(&instances);
+        assert_eq!(total, 3);
+        assert_eq!(matching[0].domain, "c.example");
+        assert_eq!(matching.len(), 1);
+        let (total, matching) = page(usize::MAX, 2).apply(&instances);
+        assert_eq!(total, 3);
+        assert!(matching.is_empty());
+    }
+}
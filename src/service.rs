@@ -0,0 +1,95 @@
+use crate::crawl::CrawlResult;
+use crate::{CrawlOutput, Crawler};
+use anyhow::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+use tower_service::Service;
+
+/// What to crawl, with the settings of the crawler behind `CrawlService`
+#[derive(Debug, Clone)]
+pub enum CrawlRequest {
+    /// Only this instance, without following its links
+    Instance(String),
+    /// The network reachable from these instances, up to the maximum distance
+    Seeds(Vec<String>),
+}
+
+/// Runs crawls as a `tower` service, so that the crawler can be embedded behind middleware for
+/// timeouts, rate limits or metrics. Each request starts a separate crawl, which runs while the
+/// response future is polled and stops when it is dropped, eg by a timeout.
+#[derive(Debug, Clone)]
+pub struct CrawlService {
+    crawler: Crawler,
+    results: Option<mpsc::UnboundedSender<CrawlResult>>,
+}
+
+impl CrawlService {
+    /// Checkpoints and progress updates of the crawler are disabled, as crawls of different
+    /// requests would mix them up
+    pub fn new(crawler: Crawler) -> Self {
+        CrawlService {
+            crawler: crawler.checkpoint(None).progress(None),
+            results: None,
+        }
+    }
+
+    /// Send each crawled instance as soon as it is available, in addition to the complete output
+    /// of the response
+    pub fn results(mut self, results: mpsc::UnboundedSender<CrawlResult>) -> Self {
+        self.results = Some(results);
+        self
+    }
+}
+
+impl Service<CrawlRequest> for CrawlService {
+    type Response = CrawlOutput;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<CrawlOutput, Error>> + Send>>;
+
+    /// Always ready, crawls can run in parallel
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: CrawlRequest) -> Self::Future {
+        let crawler = match request {
+            CrawlRequest::Instance(domain) => self
+                .crawler
+                .clone()
+                .start_instances(vec![domain])
+                .max_distance(0),
+            CrawlRequest::Seeds(domains) => self.crawler.clone().start_instances(domains),
+        };
+        let results = self.results.clone();
+        Box::pin(async move {
+            crawler
+                .run_streaming(|result| {
+                    if let Some(results) = &results {
+                        // The receiver may only be interested in some of the results
+                        results.send(result.clone()).ok();
+                    }
+                })
+                .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MinVersion;
+
+    #[tokio::test]
+    async fn response_is_the_complete_crawl() -> Result<(), Error> {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let crawler = Crawler::new().min_version(MinVersion::Any);
+        let mut service = CrawlService::new(crawler).results(sender);
+        let output = service.call(CrawlRequest::Seeds(vec![])).await?;
+        assert!(output.results.is_empty());
+        drop(service);
+        assert!(receiver.recv().await.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Number of fields listed for each instance
+const FIELDS_PER_INSTANCE: usize = 5;
+
+/// Which instances and fields take up most of the size of a JSON output
+#[derive(Debug, Serialize)]
+pub struct SizeReport {
+    /// Serialized size of all instance details, in bytes
+    pub total_bytes: usize,
+    /// Fields summed over all instances, largest first
+    pub fields: Vec<FieldSize>,
+    /// Largest instances first
+    pub instances: Vec<InstanceSize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InstanceSize {
+    pub domain: String,
+    pub bytes: usize,
+    /// Largest fields of this instance
+    pub fields: Vec<FieldSize>,
+}
+
+/// Serialized size of a field, with nested fields separated by dots, eg `site_info.taglines`
+#[derive(Debug, Serialize)]
+pub struct FieldSize {
+    pub field: String,
+    pub bytes: usize,
+}
+
+/// Measure the instance details of a JSON output, and list the `top` largest instances
+pub fn size_report(output: &Value, top: usize) -> SizeReport {
+    let mut totals: HashMap<String, usize> = HashMap::new();
+    let mut instances: Vec<_> = output["instance_details"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|instance| {
+            let mut fields = field_sizes(instance);
+            for field in &fields {
+                *totals.entry(field.field.clone()).or_default() += field.bytes;
+            }
+            fields.sort_by_key(|f| Reverse(f.bytes));
+            fields.truncate(FIELDS_PER_INSTANCE);
+            InstanceSize {
+                domain: instance["domain"].as_str().unwrap_or_default().to_string(),
+                bytes: serialized_size(instance),
+                fields,
+            }
+        })
+        .collect();
+    instances.sort_by_key(|i| Reverse(i.bytes));
+    let total_bytes = instances.iter().map(|i| i.bytes).sum();
+    instances.truncate(top);
+    let mut fields: Vec<_> = totals
+        .into_iter()
+        .map(|(field, bytes)| FieldSize { field, bytes })
+        .collect();
+    fields.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.field.cmp(&b.field)));
+    SizeReport {
+        total_bytes,
+        fields,
+        instances,
+    }
+}
+
+/// Sizes of the top level fields, and of the fields nested one level below them
+fn field_sizes(instance: &Value) -> Vec<FieldSize> {
+    let mut sizes = vec![];
+    for (name, value) in instance.as_object().into_iter().flatten() {
+        sizes.push(FieldSize {
+            field: name.clone(),
+            bytes: serialized_size(value),
+        });
+        for (nested, value) in value.as_object().into_iter().flatten() {
+            sizes.push(FieldSize {
+                field: format!("{name}.{nested}"),
+                bytes: serialized_size(value),
+            });
+        }
+    }
+    sizes
+}
+
+fn serialized_size(value: &Value) -> usize {
+    serde_json::to_vec(value).map_or(0, |v| v.len())
+}
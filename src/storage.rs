@@ -0,0 +1,631 @@
+use crate::aggregate::TotalStats;
+use crate::crawl::CrawlResult;
+use crate::rollup::Sample;
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Timelike, Utc};
+#[cfg(feature = "duckdb")]
+use chrono::{NaiveDateTime, TimeZone};
+#[cfg(feature = "duckdb")]
+use once_cell::sync::OnceCell;
+use rusqlite::{params, Connection};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+#[cfg(feature = "duckdb")]
+use std::sync::{mpsc, Arc, Mutex};
+#[cfg(feature = "duckdb")]
+use std::thread::{self, JoinHandle};
+use ulid::Ulid;
+
+/// Database where the results of each crawl are saved, to track growth over time
+#[derive(Debug, Clone)]
+pub enum Store {
+    Sqlite(PathBuf),
+    /// Same tables as SQLite with typed columns, for analytical queries over the history
+    #[cfg(feature = "duckdb")]
+    DuckDb(DuckDbFile),
+}
+
+impl FromStr for Store {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("sqlite", path)) => Ok(Store::Sqlite(path.into())),
+            #[cfg(feature = "duckdb")]
+            Some(("duckdb", path)) => Ok(Store::DuckDb(DuckDbFile::new(path.into()))),
+            #[cfg(not(feature = "duckdb"))]
+            Some(("duckdb", _)) => Err(anyhow!(
+                "duckdb stores require a crawler built with the duckdb feature"
+            )),
+            _ => Err(anyhow!(
+                "unsupported store {s}, expected sqlite:<path> or duckdb:<path>"
+            )),
+        }
+    }
+}
+
+/// Network totals and per-instance counts
+const SAMPLES: &str = "SELECT crawled_at, NULL, total_users, users_active_month FROM crawl
+    UNION ALL
+    SELECT crawled_at, domain, total_users, users_active_month FROM instance";
+
+/// Network totals and per-instance counts of one crawl by run id
+const RUN_SAMPLES: &str = "SELECT crawled_at, NULL, total_users, users_active_month FROM crawl
+    WHERE run_id = ?1
+    UNION ALL
+    SELECT i.crawled_at, i.domain, i.total_users, i.users_active_month
+    FROM instance i JOIN crawl c ON c.crawled_at = i.crawled_at
+    WHERE c.run_id = ?1";
+
+const KNOWN_INSTANCES: &str = "SELECT DISTINCT domain FROM instance";
+
+const TOP_INSTANCES: &str = "SELECT domain FROM instance
+    WHERE crawled_at = (SELECT MAX(crawled_at) FROM crawl)
+    ORDER BY users_active_month DESC, domain
+    LIMIT ?1";
+
+/// Connection to either database, with the tables created
+enum Db {
+    Sqlite(Connection),
+    #[cfg(feature = "duckdb")]
+    DuckDb(duckdb::Connection),
+}
+
+/// Runs a query on either database and collects the rows, mapped with the function for SQLite or
+/// DuckDB. Both crates have the same API, but not the same types.
+macro_rules! query {
+    ($db:expr, $sql:expr, $params:expr, $sqlite_row:expr, $duckdb_row:expr) => {
+        match $db {
+            Db::Sqlite(conn) => conn
+                .prepare($sql)?
+                .query_map($params, $sqlite_row)?
+                .collect::<Result<_, _>>()?,
+            #[cfg(feature = "duckdb")]
+            Db::DuckDb(conn) => conn
+                .prepare($sql)?
+                .query_map($params, $duckdb_row)?
+                .collect::<Result<_, _>>()?,
+        }
+    };
+}
+
+impl Store {
+    fn open(&self) -> Result<Db, Error> {
+        match self {
+            Store::Sqlite(path) => Ok(Db::Sqlite(open_sqlite(path)?)),
+            #[cfg(feature = "duckdb")]
+            Store::DuckDb(file) => Ok(Db::DuckDb(file.connect()?)),
+        }
+    }
+
+    /// Write totals and per-instance counts of a crawl, keyed by the time the crawl started
+    pub fn save(&self, crawled_at: DateTime<Utc>, stats: &TotalStats) -> Result<(), Error> {
+        let writer = self.writer(crawled_at)?;
+        for i in &stats.instance_details {
+            writer.add(i);
+        }
+        writer.finish(stats)
+    }
+
+    /// Start saving a crawl while it runs. DuckDB writes each instance passed to
+    /// `CrawlWriter::add` right away, SQLite writes all instances in `CrawlWriter::finish`. Nothing
+    /// is saved if the writer is dropped before it is finished.
+    pub fn writer(&self, crawled_at: DateTime<Utc>) -> Result<CrawlWriter, Error> {
+        match self {
+            Store::Sqlite(path) => Ok(CrawlWriter::Sqlite(path.clone(), crawled_at)),
+            #[cfg(feature = "duckdb")]
+            Store::DuckDb(file) => Ok(CrawlWriter::DuckDb(DuckDbWriter::start(
+                file.connect()?,
+                crawled_at,
+            ))),
+        }
+    }
+
+    /// Network totals and per-instance counts of all stored crawls
+    pub fn samples(&self) -> Result<Vec<Sample>, Error> {
+        Ok(query!(
+            self.open()?,
+            SAMPLES,
+            [],
+            sqlite_sample,
+            duckdb_sample
+        ))
+    }
+
+    /// Network totals and per-instance counts of the crawl with the given run id, eg to trace a
+    /// bad data point back to its crawl
+    pub fn run_samples(&self, run_id: &str) -> Result<Vec<Sample>, Error> {
+        Ok(query!(
+            self.open()?,
+            RUN_SAMPLES,
+            [run_id],
+            sqlite_sample,
+            duckdb_sample
+        ))
+    }
+
+    /// Domains of all instances which were crawled successfully in any previous crawl
+    pub fn known_instances(&self) -> Result<HashSet<String>, Error> {
+        Ok(query!(
+            self.open()?,
+            KNOWN_INSTANCES,
+            [],
+            |row| row.get(0),
+            |row| row.get(0)
+        ))
+    }
+
+    /// Domains of the instances with the most monthly active users in the latest stored crawl,
+    /// largest first
+    pub fn top_instances(&self, limit: usize) -> Result<Vec<String>, Error> {
+        Ok(query!(
+            self.open()?,
+            TOP_INSTANCES,
+            [limit],
+            |row| row.get(0),
+            |row| row.get(0)
+        ))
+    }
+}
+
+/// Saves a crawl while it runs, see `Store::writer`
+pub enum CrawlWriter {
+    Sqlite(PathBuf, DateTime<Utc>),
+    #[cfg(feature = "duckdb")]
+    DuckDb(DuckDbWriter),
+}
+
+impl CrawlWriter {
+    /// Errors are returned by `finish`
+    #[cfg_attr(not(feature = "duckdb"), allow(unused_variables))]
+    pub fn add(&self, result: &CrawlResult) {
+        match self {
+            CrawlWriter::Sqlite(..) => {}
+            #[cfg(feature = "duckdb")]
+            CrawlWriter::DuckDb(writer) => writer.add(result),
+        }
+    }
+
+    /// Write the totals of the crawl, and all its instances which are not written yet
+    pub fn finish(self, stats: &TotalStats) -> Result<(), Error> {
+        match self {
+            CrawlWriter::Sqlite(path, crawled_at) => {
+                save_sqlite(&mut open_sqlite(&path)?, crawled_at, stats)
+            }
+            #[cfg(feature = "duckdb")]
+            CrawlWriter::DuckDb(writer) => writer.finish(stats),
+        }
+    }
+}
+
+/// Counts of an instance as they are stored
+struct StoredInstance {
+    domain: String,
+    version: String,
+    total_users: i64,
+    users_active_day: i64,
+    users_active_week: i64,
+    users_active_month: i64,
+    users_active_halfyear: i64,
+    posts: i64,
+    comments: i64,
+}
+
+impl StoredInstance {
+    fn new(i: &CrawlResult) -> Self {
+        StoredInstance {
+            domain: i.domain.clone(),
+            version: i.site_info.version(),
+            total_users: i.site_info.total_users(),
+            users_active_day: i.site_info.users_active_day(),
+            users_active_week: i.site_info.users_active_week(),
+            users_active_month: i.site_info.users_active_month(),
+            users_active_halfyear: i.site_info.users_active_half_year(),
+            posts: i.site_info.posts(),
+            comments: i.site_info.comments(),
+        }
+    }
+}
+
+fn sqlite_sample(row: &rusqlite::Row) -> rusqlite::Result<Sample> {
+    Ok(Sample {
+        crawled_at: row.get(0)?,
+        domain: row.get(1)?,
+        total_users: row.get(2)?,
+        users_active_month: row.get(3)?,
+    })
+}
+
+fn open_sqlite(path: &Path) -> Result<Connection, Error> {
+    let conn = Connection::open(path)?;
+    create_tables(&conn)?;
+    Ok(conn)
+}
+
+fn create_tables(conn: &Connection) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS crawl (
+            crawled_at TEXT PRIMARY KEY,
+            crawled_instances INTEGER NOT NULL,
+            failed_instances INTEGER NOT NULL,
+            total_users INTEGER NOT NULL,
+            users_active_day INTEGER NOT NULL,
+            users_active_week INTEGER NOT NULL,
+            users_active_month INTEGER NOT NULL,
+            users_active_halfyear INTEGER NOT NULL,
+            run_id TEXT,
+            utc_day TEXT,
+            seconds_after_midnight INTEGER
+        );
+        CREATE TABLE IF NOT EXISTS instance (
+            crawled_at TEXT NOT NULL REFERENCES crawl (crawled_at),
+            domain TEXT NOT NULL,
+            version TEXT NOT NULL,
+            total_users INTEGER NOT NULL,
+            users_active_day INTEGER NOT NULL,
+            users_active_week INTEGER NOT NULL,
+            users_active_month INTEGER NOT NULL,
+            users_active_halfyear INTEGER NOT NULL,
+            posts INTEGER NOT NULL,
+            comments INTEGER NOT NULL,
+            PRIMARY KEY (crawled_at, domain)
+        );",
+    )?;
+    add_run_ids(conn)?;
+    add_day_alignment(conn)
+}
+
+/// Add the run id column to databases which were created before it existed, and give the crawls
+/// stored there an id based on their time only. Running this again gives the same ids.
+fn add_run_ids(conn: &Connection) -> Result<(), Error> {
+    if conn.prepare("SELECT run_id FROM crawl LIMIT 0").is_err() {
+        conn.execute("ALTER TABLE crawl ADD COLUMN run_id TEXT", [])?;
+    }
+    let mut select = conn.prepare("SELECT crawled_at FROM crawl WHERE run_id IS NULL")?;
+    let missing = select
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for crawled_at in missing {
+        let millis = DateTime::parse_from_rfc3339(&crawled_at)?.timestamp_millis();
+        let run_id = Ulid::from_parts(millis as u64, 0).to_string();
+        conn.execute(
+            "UPDATE crawl SET run_id = ?1 WHERE crawled_at = ?2",
+            params![run_id, crawled_at],
+        )?;
+    }
+    Ok(())
+}
+
+/// Add the UTC day columns to databases which were created before they existed, and fill them
+/// from the crawl times
+fn add_day_alignment(conn: &Connection) -> Result<(), Error> {
+    if conn.prepare("SELECT utc_day FROM crawl LIMIT 0").is_err() {
+        conn.execute_batch(
+            "ALTER TABLE crawl ADD COLUMN utc_day TEXT;
+            ALTER TABLE crawl ADD COLUMN seconds_after_midnight INTEGER;",
+        )?;
+    }
+    let mut select = conn.prepare("SELECT crawled_at FROM crawl WHERE utc_day IS NULL")?;
+    let missing = select
+        .query_map([], |row| row.get::<_, DateTime<Utc>>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    for crawled_at in missing {
+        let (utc_day, seconds_after_midnight) = day_alignment(crawled_at);
+        conn.execute(
+            "UPDATE crawl SET utc_day = ?1, seconds_after_midnight = ?2 WHERE crawled_at = ?3",
+            params![utc_day, seconds_after_midnight, crawled_at.to_rfc3339()],
+        )?;
+    }
+    Ok(())
+}
+
+/// UTC day of the crawl, eg 2024-02-01, and how long after the start of that day it started
+fn day_alignment(crawled_at: DateTime<Utc>) -> (String, u32) {
+    (
+        crawled_at.format("%Y-%m-%d").to_string(),
+        crawled_at.num_seconds_from_midnight(),
+    )
+}
+
+fn save_sqlite(
+    conn: &mut Connection,
+    crawled_at: DateTime<Utc>,
+    stats: &TotalStats,
+) -> Result<(), Error> {
+    let (utc_day, seconds_after_midnight) = day_alignment(crawled_at);
+    let crawled_at = crawled_at.to_rfc3339();
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO crawl VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        params![
+            crawled_at,
+            stats.crawled_instances,
+            stats.failed_instances.len(),
+            stats.total_users,
+            stats.users_active_day,
+            stats.users_active_week,
+            stats.users_active_month,
+            stats.users_active_halfyear,
+            stats.run_id,
+            utc_day,
+            seconds_after_midnight,
+        ],
+    )?;
+    {
+        let mut insert =
+            tx.prepare("INSERT INTO instance VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)")?;
+        for i in stats.instance_details.iter().map(StoredInstance::new) {
+            insert.execute(params![
+                crawled_at,
+                i.domain,
+                i.version,
+                i.total_users,
+                i.users_active_day,
+                i.users_active_week,
+                i.users_active_month,
+                i.users_active_halfyear,
+                i.posts,
+                i.comments,
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// DuckDB database of a store. A file can only be opened once, so all connections of the crawler
+/// share the database, which stays open until the crawler exits.
+#[cfg(feature = "duckdb")]
+#[derive(Debug, Clone)]
+pub struct DuckDbFile {
+    path: PathBuf,
+    database: Arc<OnceCell<Mutex<duckdb::Connection>>>,
+}
+
+#[cfg(feature = "duckdb")]
+impl DuckDbFile {
+    fn new(path: PathBuf) -> Self {
+        DuckDbFile {
+            path,
+            database: Default::default(),
+        }
+    }
+
+    /// New connection to the database, which is opened and set up on first use
+    fn connect(&self) -> Result<duckdb::Connection, Error> {
+        let database = self.database.get_or_try_init(|| -> Result<_, Error> {
+            let conn = duckdb::Connection::open(&self.path)?;
+            create_duckdb_tables(&conn)?;
+            Ok(Mutex::new(conn))
+        })?;
+        Ok(database.lock().unwrap().try_clone()?)
+    }
+}
+
+/// Same tables as `create_tables`, with timestamps, dates and integer types instead of text.
+/// DuckDB stores are new, so no columns have to be added to older databases.
+#[cfg(feature = "duckdb")]
+fn create_duckdb_tables(conn: &duckdb::Connection) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS crawl (
+            crawled_at TIMESTAMP PRIMARY KEY,
+            crawled_instances INTEGER NOT NULL,
+            failed_instances INTEGER NOT NULL,
+            total_users BIGINT NOT NULL,
+            users_active_day BIGINT NOT NULL,
+            users_active_week BIGINT NOT NULL,
+            users_active_month BIGINT NOT NULL,
+            users_active_halfyear BIGINT NOT NULL,
+            run_id VARCHAR NOT NULL,
+            utc_day DATE NOT NULL,
+            seconds_after_midnight INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS instance (
+            crawled_at TIMESTAMP NOT NULL REFERENCES crawl (crawled_at),
+            domain VARCHAR NOT NULL,
+            version VARCHAR NOT NULL,
+            total_users BIGINT NOT NULL,
+            users_active_day BIGINT NOT NULL,
+            users_active_week BIGINT NOT NULL,
+            users_active_month BIGINT NOT NULL,
+            users_active_halfyear BIGINT NOT NULL,
+            posts BIGINT NOT NULL,
+            comments BIGINT NOT NULL,
+            PRIMARY KEY (crawled_at, domain)
+        );",
+    )?;
+    Ok(())
+}
+
+/// Timestamps are stored in UTC without time zone
+#[cfg(feature = "duckdb")]
+fn duckdb_sample(row: &duckdb::Row) -> duckdb::Result<Sample> {
+    Ok(Sample {
+        crawled_at: Utc.from_utc_datetime(&row.get::<_, NaiveDateTime>(0)?),
+        domain: row.get(1)?,
+        total_users: row.get(2)?,
+        users_active_month: row.get(3)?,
+    })
+}
+
+#[cfg(feature = "duckdb")]
+enum DuckDbWrite {
+    Instance(StoredInstance),
+    /// Totals of the crawl, after all instances
+    Finish {
+        crawled_instances: i32,
+        failed_instances: usize,
+        total_users: i64,
+        users_active_day: i64,
+        users_active_week: i64,
+        users_active_month: i64,
+        users_active_halfyear: i64,
+        run_id: String,
+    },
+}
+
+/// Appends instances to DuckDB as they are crawled, in a thread which owns the connection for
+/// the transaction of the crawl
+#[cfg(feature = "duckdb")]
+pub struct DuckDbWriter {
+    writes: mpsc::Sender<DuckDbWrite>,
+    thread: JoinHandle<Result<(), Error>>,
+}
+
+#[cfg(feature = "duckdb")]
+impl DuckDbWriter {
+    fn start(conn: duckdb::Connection, crawled_at: DateTime<Utc>) -> Self {
+        let (writes, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || write_duckdb(conn, crawled_at, receiver));
+        DuckDbWriter { writes, thread }
+    }
+
+    fn add(&self, result: &CrawlResult) {
+        // A failed thread returns its error in `finish`
+        let _ = self
+            .writes
+            .send(DuckDbWrite::Instance(StoredInstance::new(result)));
+    }
+
+    fn finish(self, stats: &TotalStats) -> Result<(), Error> {
+        let _ = self.writes.send(DuckDbWrite::Finish {
+            crawled_instances: stats.crawled_instances,
+            failed_instances: stats.failed_instances.len(),
+            total_users: stats.total_users,
+            users_active_day: stats.users_active_day,
+            users_active_week: stats.users_active_week,
+            users_active_month: stats.users_active_month,
+            users_active_halfyear: stats.users_active_halfyear,
+            run_id: stats.run_id.clone(),
+        });
+        drop(self.writes);
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err(anyhow!("DuckDB writer panicked")))
+    }
+}
+
+/// The crawl is inserted first, as the instances reference it, and its totals are filled in at
+/// the end. Instances are written with an appender, which is much faster than single inserts in
+/// DuckDB. Without `Finish`, the transaction is rolled back.
+#[cfg(feature = "duckdb")]
+fn write_duckdb(
+    mut conn: duckdb::Connection,
+    crawled_at: DateTime<Utc>,
+    writes: mpsc::Receiver<DuckDbWrite>,
+) -> Result<(), Error> {
+    let utc_day = crawled_at.date_naive();
+    let seconds_after_midnight = crawled_at.num_seconds_from_midnight();
+    let crawled_at = crawled_at.naive_utc();
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO crawl VALUES (?, 0, 0, 0, 0, 0, 0, 0, '', ?, ?)",
+        duckdb::params![crawled_at, utc_day, seconds_after_midnight],
+    )?;
+    let mut appender = tx.appender("instance")?;
+    for write in writes {
+        match write {
+            DuckDbWrite::Instance(i) => appender.append_row(duckdb::params![
+                crawled_at,
+                i.domain,
+                i.version,
+                i.total_users,
+                i.users_active_day,
+                i.users_active_week,
+                i.users_active_month,
+                i.users_active_halfyear,
+                i.posts,
+                i.comments,
+            ])?,
+            DuckDbWrite::Finish {
+                crawled_instances,
+                failed_instances,
+                total_users,
+                users_active_day,
+                users_active_week,
+                users_active_month,
+                users_active_halfyear,
+                run_id,
+            } => {
+                appender.flush();
+                drop(appender);
+                tx.execute(
+                    "UPDATE crawl SET crawled_instances = ?, failed_instances = ?,
+                        total_users = ?, users_active_day = ?, users_active_week = ?,
+                        users_active_month = ?, users_active_halfyear = ?, run_id = ?
+                    WHERE crawled_at = ?",
+                    duckdb::params![
+                        crawled_instances,
+                        failed_instances,
+                        total_users,
+                        users_active_day,
+                        users_active_week,
+                        users_active_month,
+                        users_active_halfyear,
+                        run_id,
+                        crawled_at,
+                    ],
+                )?;
+                tx.commit()?;
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::aggregate;
+    use crate::crawl::tests::result_json;
+    use crate::CrawlOutput;
+    use chrono::TimeZone;
+
+    fn stats() -> TotalStats {
+        aggregate(CrawlOutput {
+            results: vec![serde_json::from_value(result_json()).unwrap()],
+            run_id: "run".to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn round_trip(store: &Store) {
+        let stats = stats();
+        let crawled_at = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let writer = store.writer(crawled_at).unwrap();
+        for i in &stats.instance_details {
+            writer.add(i);
+        }
+        writer.finish(&stats).unwrap();
+
+        let domain = stats.instance_details[0].domain.clone();
+        let samples = store.run_samples("run").unwrap();
+        assert_eq!(samples.len(), 2);
+        assert!(samples.iter().all(|s| s.crawled_at == crawled_at));
+        assert_eq!(store.samples().unwrap().len(), 2);
+        assert!(store.run_samples("other").unwrap().is_empty());
+        assert_eq!(store.top_instances(10).unwrap(), [domain.clone()]);
+        assert!(store.known_instances().unwrap().contains(&domain));
+    }
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{name}", Ulid::new()))
+    }
+
+    #[test]
+    fn sqlite_round_trip() {
+        let path = path("crawls.sqlite");
+        round_trip(&Store::Sqlite(path.clone()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "duckdb")]
+    #[test]
+    fn duckdb_round_trip() {
+        let path = path("crawls.duckdb");
+        round_trip(&Store::DuckDb(DuckDbFile::new(path.clone())));
+        std::fs::remove_file(path).unwrap();
+    }
+}
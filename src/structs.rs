@@ -1,10 +1,16 @@
+use chrono::{DateTime, Utc};
+use lemmy_api_common_v019::lemmy_db_schema::newtypes::LanguageId;
+use lemmy_api_common_v019::lemmy_db_schema::source::language::Language;
+use lemmy_api_common_v019::lemmy_db_schema::RegistrationMode;
 use lemmy_api_common_v019::site::{
-    FederatedInstances as FederatedInstances019,
     GetFederatedInstancesResponse as GetFederatedInstancesResponse019,
     GetSiteResponse as GetSiteResponse019,
 };
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -14,6 +20,58 @@ pub struct NodeInfo {
     pub protocols: Vec<String>,
     pub usage: NodeInfoUsage,
     pub open_registrations: bool,
+    /// Free form, software specific details
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+}
+
+impl NodeInfo {
+    /// Set usage counts below the threshold to 0
+    pub fn suppress_small_counts(&mut self, threshold: i64) {
+        let usage = &mut self.usage;
+        for count in [
+            &mut usage.users.total,
+            &mut usage.users.active_halfyear,
+            &mut usage.users.active_month,
+            &mut usage.posts,
+            &mut usage.comments,
+        ] {
+            suppress_count(count, threshold);
+        }
+    }
+}
+
+fn suppress_count(count: &mut i64, threshold: i64) {
+    if *count < threshold {
+        *count = 0;
+    }
+}
+
+/// Response of `/.well-known/nodeinfo`, which links to the actual nodeinfo documents
+#[derive(Deserialize, Debug)]
+pub struct NodeInfoWellKnown {
+    pub links: Vec<NodeInfoWellKnownLink>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NodeInfoWellKnownLink {
+    /// Schema of the linked document, eg `http://nodeinfo.diaspora.software/ns/schema/2.1`
+    pub rel: String,
+    pub href: Url,
+}
+
+impl NodeInfoWellKnown {
+    /// Link to the nodeinfo document with the newest schema version
+    pub fn newest(&self) -> Option<&Url> {
+        self.links
+            .iter()
+            .filter(|l| {
+                l.rel
+                    .starts_with("http://nodeinfo.diaspora.software/ns/schema/")
+            })
+            .max_by(|a, b| a.rel.cmp(&b.rel))
+            .map(|l| &l.href)
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -26,9 +84,9 @@ pub struct NodeInfoSoftware {
 #[serde(rename_all = "camelCase", default)]
 pub struct NodeInfoUsage {
     pub users: NodeInfoUsers,
-    #[serde(rename(deserialize = "localPosts"))]
+    #[serde(alias = "localPosts")]
     pub posts: i64,
-    #[serde(rename(deserialize = "localComments"))]
+    #[serde(alias = "localComments")]
     pub comments: i64,
 }
 
@@ -40,66 +98,825 @@ pub struct NodeInfoUsers {
     pub active_month: i64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Response of `/api/v3/community/list`. Only the fields which the crawler reads are defined, so
+/// that it works with all Lemmy versions and PieFed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListCommunitiesResponse {
+    pub communities: Vec<CommunityView>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommunityView {
+    pub community: Community,
+    pub counts: CommunityAggregates,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Community {
+    pub name: String,
+    pub title: String,
+    pub actor_id: Url,
+    #[serde(default)]
+    pub nsfw: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommunityAggregates {
+    pub subscribers: i64,
+    pub posts: i64,
+    pub comments: i64,
+    #[serde(default)]
+    pub users_active_month: i64,
+}
+
+impl CommunityAggregates {
+    /// Set subscriber and activity counts below the threshold to 0
+    pub fn suppress_small_counts(&mut self, threshold: i64) {
+        for count in [
+            &mut self.subscribers,
+            &mut self.posts,
+            &mut self.comments,
+            &mut self.users_active_month,
+        ] {
+            suppress_count(count, threshold);
+        }
+    }
+}
+
+/// Response of `/api/info` on Mbin and Kbin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MbinInfo {
+    pub software_name: String,
+    pub software_version: String,
+    pub website_domain: String,
+    pub website_title: Option<String>,
+    pub website_contact_email: Option<String>,
+    #[serde(default)]
+    pub website_open_registrations: bool,
+    pub website_default_lang: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum GetSiteResponse {
-    V019(GetSiteResponse019),
+    V019(Box<GetSiteResponse019>),
+    V020(GetSiteResponse020),
+    PieFed(PieFedSite),
+    /// Deserialization is attempted in order, so this needs to come last as it has the fewest
+    /// fields and would also match newer responses
+    V018(GetSiteResponse018),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum GetFederatedInstancesResponse {
     V019(GetFederatedInstancesResponse019),
+    V020(GetFederatedInstancesResponse020),
+    V018(GetFederatedInstancesResponse018),
 }
 
 impl GetSiteResponse {
+    /// Set user and activity counts below the threshold to 0
+    pub fn suppress_small_counts(&mut self, threshold: i64) {
+        let counts = match self {
+            GetSiteResponse::V019(s) => {
+                let c = &mut s.site_view.counts;
+                [
+                    &mut c.users,
+                    &mut c.users_active_day,
+                    &mut c.users_active_week,
+                    &mut c.users_active_month,
+                    &mut c.users_active_half_year,
+                    &mut c.posts,
+                    &mut c.comments,
+                ]
+            }
+            GetSiteResponse::V018(s) => {
+                let c = &mut s.site_view.counts;
+                [
+                    &mut c.users,
+                    &mut c.users_active_day,
+                    &mut c.users_active_week,
+                    &mut c.users_active_month,
+                    &mut c.users_active_half_year,
+                    &mut c.posts,
+                    &mut c.comments,
+                ]
+            }
+            GetSiteResponse::PieFed(s) => {
+                let c = &mut s.site_view.counts;
+                [
+                    &mut c.users,
+                    &mut c.users_active_day,
+                    &mut c.users_active_week,
+                    &mut c.users_active_month,
+                    &mut c.users_active_half_year,
+                    &mut c.posts,
+                    &mut c.comments,
+                ]
+            }
+            GetSiteResponse::V020(s) => {
+                let c = &mut s.site_view.local_site;
+                [
+                    &mut c.users,
+                    &mut c.users_active_day,
+                    &mut c.users_active_week,
+                    &mut c.users_active_month,
+                    &mut c.users_active_half_year,
+                    &mut c.posts,
+                    &mut c.comments,
+                ]
+            }
+        };
+        for count in counts {
+            suppress_count(count, threshold);
+        }
+    }
+
+    /// Lemmy API major version of the response, or the software for forks with their own API
+    pub fn api_version(&self) -> &'static str {
+        match self {
+            GetSiteResponse::V019(_) | GetSiteResponse::V018(_) => "v3",
+            GetSiteResponse::V020(_) => "v4",
+            GetSiteResponse::PieFed(_) => "piefed",
+        }
+    }
+
     pub fn version(&self) -> String {
         match self {
             GetSiteResponse::V019(s) => s.version.clone(),
+            GetSiteResponse::V018(s) => s.version.clone(),
+            GetSiteResponse::PieFed(s) => s.version.clone(),
+            GetSiteResponse::V020(s) => s.version.clone(),
         }
     }
 
     pub fn total_users(&self) -> i64 {
         match self {
             GetSiteResponse::V019(s) => s.site_view.counts.users,
+            GetSiteResponse::V018(s) => s.site_view.counts.users,
+            GetSiteResponse::PieFed(s) => s.site_view.counts.users,
+            GetSiteResponse::V020(s) => s.site_view.local_site.users,
+        }
+    }
+
+    pub fn posts(&self) -> i64 {
+        match self {
+            GetSiteResponse::V019(s) => s.site_view.counts.posts,
+            GetSiteResponse::V018(s) => s.site_view.counts.posts,
+            GetSiteResponse::PieFed(s) => s.site_view.counts.posts,
+            GetSiteResponse::V020(s) => s.site_view.local_site.posts,
+        }
+    }
+
+    pub fn comments(&self) -> i64 {
+        match self {
+            GetSiteResponse::V019(s) => s.site_view.counts.comments,
+            GetSiteResponse::V018(s) => s.site_view.counts.comments,
+            GetSiteResponse::PieFed(s) => s.site_view.counts.comments,
+            GetSiteResponse::V020(s) => s.site_view.local_site.comments,
         }
     }
 
     pub fn users_active_day(&self) -> i64 {
         match self {
             GetSiteResponse::V019(s) => s.site_view.counts.users_active_day,
+            GetSiteResponse::V018(s) => s.site_view.counts.users_active_day,
+            GetSiteResponse::PieFed(s) => s.site_view.counts.users_active_day,
+            GetSiteResponse::V020(s) => s.site_view.local_site.users_active_day,
         }
     }
 
     pub fn users_active_week(&self) -> i64 {
         match self {
             GetSiteResponse::V019(s) => s.site_view.counts.users_active_week,
+            GetSiteResponse::V018(s) => s.site_view.counts.users_active_week,
+            GetSiteResponse::PieFed(s) => s.site_view.counts.users_active_week,
+            GetSiteResponse::V020(s) => s.site_view.local_site.users_active_week,
         }
     }
 
     pub fn users_active_month(&self) -> i64 {
         match self {
             GetSiteResponse::V019(s) => s.site_view.counts.users_active_month,
+            GetSiteResponse::V018(s) => s.site_view.counts.users_active_month,
+            GetSiteResponse::PieFed(s) => s.site_view.counts.users_active_month,
+            GetSiteResponse::V020(s) => s.site_view.local_site.users_active_month,
         }
     }
 
     pub fn users_active_half_year(&self) -> i64 {
         match self {
             GetSiteResponse::V019(s) => s.site_view.counts.users_active_half_year,
+            GetSiteResponse::V018(s) => s.site_view.counts.users_active_half_year,
+            GetSiteResponse::PieFed(s) => s.site_view.counts.users_active_half_year,
+            GetSiteResponse::V020(s) => s.site_view.local_site.users_active_half_year,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            GetSiteResponse::V019(s) => s.site_view.site.name.clone(),
+            GetSiteResponse::V018(s) => s.site_view.site.name.clone(),
+            GetSiteResponse::PieFed(s) => s.site_view.site.name.clone(),
+            GetSiteResponse::V020(s) => s.site_view.site.name.clone(),
+        }
+    }
+
+    pub fn description(&self) -> Option<String> {
+        match self {
+            GetSiteResponse::V019(s) => s.site_view.site.description.clone(),
+            GetSiteResponse::V018(s) => s.site_view.site.description.clone(),
+            GetSiteResponse::PieFed(s) => s.site_view.site.description.clone(),
+            GetSiteResponse::V020(s) => s.site_view.site.description.clone(),
+        }
+    }
+
+    pub fn icon(&self) -> Option<Url> {
+        match self {
+            GetSiteResponse::V019(s) => s.site_view.site.icon.as_ref().map(|i| i.inner().clone()),
+            GetSiteResponse::V018(s) => s.site_view.site.icon.clone(),
+            GetSiteResponse::PieFed(s) => s.site_view.site.icon.clone(),
+            GetSiteResponse::V020(s) => s.site_view.site.icon.clone(),
+        }
+    }
+
+    pub fn registration_mode(&self) -> RegistrationMode {
+        match self {
+            GetSiteResponse::V019(s) => s.site_view.local_site.registration_mode,
+            GetSiteResponse::V018(s) => s.site_view.local_site.registration_mode,
+            GetSiteResponse::PieFed(s) => s.site_view.local_site.registration_mode,
+            GetSiteResponse::V020(s) => s.site_view.local_site.registration_mode,
+        }
+    }
+
+    /// Whether new users have to solve a captcha to register
+    pub fn captcha_enabled(&self) -> bool {
+        match self {
+            GetSiteResponse::V019(s) => s.site_view.local_site.captcha_enabled,
+            GetSiteResponse::V018(s) => s.site_view.local_site.captcha_enabled,
+            GetSiteResponse::PieFed(s) => s.site_view.local_site.captcha_enabled,
+            GetSiteResponse::V020(s) => s.site_view.local_site.captcha_enabled,
+        }
+    }
+
+    /// Minimum time between API requests which the instance allows, from its rate limit for
+    /// general requests. Missing if the limit is not exposed.
+    pub fn request_interval(&self) -> Option<Duration> {
+        let (requests, seconds) = match self {
+            GetSiteResponse::V019(s) => {
+                let limit = &s.site_view.local_site_rate_limit;
+                (limit.message, limit.message_per_second)
+            }
+            GetSiteResponse::V018(s) => {
+                let limit = s.site_view.local_site_rate_limit.as_ref()?;
+                (limit.message, limit.message_per_second)
+            }
+            GetSiteResponse::PieFed(_) | GetSiteResponse::V020(_) => return None,
+        };
+        (requests > 0 && seconds > 0)
+            .then(|| Duration::from_secs_f64(f64::from(seconds) / f64::from(requests)))
+    }
+
+    /// Whether the instance allows NSFW content
+    pub fn nsfw_enabled(&self) -> bool {
+        match self {
+            GetSiteResponse::V019(s) => s.site_view.local_site.enable_nsfw,
+            GetSiteResponse::V018(s) => s.site_view.local_site.enable_nsfw,
+            GetSiteResponse::PieFed(s) => s.site_view.local_site.enable_nsfw,
+            GetSiteResponse::V020(s) => s.site_view.local_site.enable_nsfw,
+        }
+    }
+
+    /// Language codes which the instance allows for discussions
+    pub fn discussion_languages(&self) -> Vec<String> {
+        let (all_languages, discussion_languages) = match self {
+            GetSiteResponse::V019(s) => (&s.all_languages, &s.discussion_languages),
+            GetSiteResponse::V018(s) => (&s.all_languages, &s.discussion_languages),
+            GetSiteResponse::PieFed(s) => (&s.all_languages, &s.discussion_languages),
+            GetSiteResponse::V020(s) => (&s.all_languages, &s.discussion_languages),
+        };
+        all_languages
+            .iter()
+            .filter(|l| discussion_languages.contains(&l.id))
+            .map(|l| l.code.clone())
+            .collect()
+    }
+
+    pub fn sidebar(&self) -> Option<String> {
+        match self {
+            GetSiteResponse::V019(s) => s.site_view.site.sidebar.clone(),
+            GetSiteResponse::V018(s) => s.site_view.site.sidebar.clone(),
+            GetSiteResponse::PieFed(s) => s.site_view.site.sidebar.clone(),
+            GetSiteResponse::V020(s) => s.site_view.site.sidebar.clone(),
+        }
+    }
+
+    /// Actor ids of instance admins
+    pub fn admins(&self) -> Vec<Url> {
+        match self {
+            GetSiteResponse::V019(s) => s
+                .admins
+                .iter()
+                .map(|a| a.person.actor_id.inner().clone())
+                .collect(),
+            GetSiteResponse::V018(s) => {
+                s.admins.iter().map(|a| a.person.actor_id.clone()).collect()
+            }
+            GetSiteResponse::PieFed(s) => {
+                s.admins.iter().map(|a| a.person.actor_id.clone()).collect()
+            }
+            GetSiteResponse::V020(s) => s.admins.iter().map(|a| a.person.ap_id.clone()).collect(),
+        }
+    }
+
+    /// Matrix user ids which instance admins have set in their profiles
+    pub fn admin_matrix_ids(&self) -> Vec<String> {
+        match self {
+            GetSiteResponse::V019(s) => s
+                .admins
+                .iter()
+                .filter_map(|a| a.person.matrix_user_id.clone())
+                .collect(),
+            GetSiteResponse::V018(s) => s
+                .admins
+                .iter()
+                .filter_map(|a| a.person.matrix_user_id.clone())
+                .collect(),
+            GetSiteResponse::PieFed(s) => s
+                .admins
+                .iter()
+                .filter_map(|a| a.person.matrix_user_id.clone())
+                .collect(),
+            GetSiteResponse::V020(s) => s
+                .admins
+                .iter()
+                .filter_map(|a| a.person.matrix_user_id.clone())
+                .collect(),
         }
     }
 
     pub fn actor_id(&self) -> Url {
         match self {
             GetSiteResponse::V019(s) => s.site_view.site.actor_id.inner().clone(),
+            GetSiteResponse::V018(s) => s.site_view.site.actor_id.clone(),
+            GetSiteResponse::PieFed(s) => s.site_view.site.actor_id.clone(),
+            GetSiteResponse::V020(s) => s.site_view.site.ap_id.clone(),
         }
     }
 }
 
 impl GetFederatedInstancesResponse {
-    pub fn federated_instances(&self) -> Option<FederatedInstances019> {
+    /// Domains of all instances which this instance federates with
+    pub fn linked(&self) -> Vec<String> {
+        match self {
+            GetFederatedInstancesResponse::V019(f) => f
+                .federated_instances
+                .iter()
+                .flat_map(|f| &f.linked)
+                .map(|i| i.instance.domain.clone())
+                .collect(),
+            GetFederatedInstancesResponse::V020(f) => f
+                .federated_instances
+                .iter()
+                .flat_map(|f| &f.linked)
+                .map(|i| i.domain.clone())
+                .collect(),
+            GetFederatedInstancesResponse::V018(f) => f
+                .federated_instances
+                .iter()
+                .flat_map(|f| &f.linked)
+                .map(|i| i.domain.clone())
+                .collect(),
+        }
+    }
+
+    /// Domains of linked instances, with the software which this instance has seen them run
+    pub fn linked_software(&self) -> Vec<(String, Option<String>)> {
+        match self {
+            GetFederatedInstancesResponse::V019(f) => f
+                .federated_instances
+                .iter()
+                .flat_map(|f| &f.linked)
+                .map(|i| (i.instance.domain.clone(), i.instance.software.clone()))
+                .collect(),
+            GetFederatedInstancesResponse::V020(f) => f
+                .federated_instances
+                .iter()
+                .flat_map(|f| &f.linked)
+                .map(|i| (i.domain.clone(), i.software.clone()))
+                .collect(),
+            GetFederatedInstancesResponse::V018(f) => f
+                .federated_instances
+                .iter()
+                .flat_map(|f| &f.linked)
+                .map(|i| (i.domain.clone(), i.software.clone()))
+                .collect(),
+        }
+    }
+
+    /// Domains on the allowlist of this instance
+    pub fn allowed(&self) -> Vec<String> {
         match self {
-            GetFederatedInstancesResponse::V019(f) => f.federated_instances.clone(),
+            GetFederatedInstancesResponse::V019(f) => f
+                .federated_instances
+                .iter()
+                .flat_map(|f| &f.allowed)
+                .map(|i| i.instance.domain.clone())
+                .collect(),
+            GetFederatedInstancesResponse::V020(f) => f
+                .federated_instances
+                .iter()
+                .flat_map(|f| &f.allowed)
+                .map(|i| i.domain.clone())
+                .collect(),
+            GetFederatedInstancesResponse::V018(f) => f
+                .federated_instances
+                .iter()
+                .flat_map(|f| &f.allowed)
+                .map(|i| i.domain.clone())
+                .collect(),
         }
     }
+
+    /// Domains blocked by this instance
+    pub fn blocked(&self) -> Vec<String> {
+        match self {
+            GetFederatedInstancesResponse::V019(f) => f
+                .federated_instances
+                .iter()
+                .flat_map(|f| &f.blocked)
+                .map(|i| i.instance.domain.clone())
+                .collect(),
+            GetFederatedInstancesResponse::V020(f) => f
+                .federated_instances
+                .iter()
+                .flat_map(|f| &f.blocked)
+                .map(|i| i.domain.clone())
+                .collect(),
+            GetFederatedInstancesResponse::V018(f) => f
+                .federated_instances
+                .iter()
+                .flat_map(|f| &f.blocked)
+                .map(|i| i.domain.clone())
+                .collect(),
+        }
+    }
+}
+
+// The lemmy_api_common releases with api v4 need a newer Rust toolchain and edition than this
+// crate, so only the fields which the crawler reads are defined here.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetSiteResponse020 {
+    pub site_view: SiteView020,
+    pub admins: Vec<PersonView020>,
+    pub version: String,
+    pub all_languages: Vec<Language>,
+    pub discussion_languages: Vec<LanguageId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiteView020 {
+    pub site: Site020,
+    pub local_site: LocalSite020,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Site020 {
+    pub name: String,
+    pub sidebar: Option<String>,
+    pub description: Option<String>,
+    pub icon: Option<Url>,
+    pub ap_id: Url,
+}
+
+/// In 0.20 the site counts were merged into local site
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalSite020 {
+    pub registration_mode: RegistrationMode,
+    #[serde(default)]
+    pub enable_nsfw: bool,
+    #[serde(default)]
+    pub captcha_enabled: bool,
+    pub users: i64,
+    pub posts: i64,
+    pub comments: i64,
+    pub users_active_day: i64,
+    pub users_active_week: i64,
+    pub users_active_month: i64,
+    pub users_active_half_year: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersonView020 {
+    pub person: Person020,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Person020 {
+    pub ap_id: Url,
+    pub matrix_user_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetFederatedInstancesResponse020 {
+    pub federated_instances: Option<FederatedInstances020>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederatedInstances020 {
+    pub linked: Vec<Instance020>,
+    pub allowed: Vec<Instance020>,
+    pub blocked: Vec<Instance020>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Instance020 {
+    pub domain: String,
+    pub software: Option<String>,
+    pub version: Option<String>,
+}
+
+// Lemmy 0.18 responses fail to deserialize with the 0.19 structs. Only the fields which the
+// crawler reads are defined here, so that these instances can still be counted.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetSiteResponse018 {
+    pub site_view: SiteView018,
+    pub admins: Vec<PersonView018>,
+    pub version: String,
+    pub all_languages: Vec<Language>,
+    pub discussion_languages: Vec<LanguageId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiteView018 {
+    pub site: Site018,
+    pub local_site: LocalSite018,
+    pub counts: SiteAggregates018,
+    #[serde(default)]
+    pub local_site_rate_limit: Option<LocalSiteRateLimit018>,
+}
+
+/// Only the limit for general API requests, other limits cover posting and registration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalSiteRateLimit018 {
+    pub message: i32,
+    pub message_per_second: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Site018 {
+    pub name: String,
+    pub sidebar: Option<String>,
+    pub description: Option<String>,
+    pub icon: Option<Url>,
+    pub actor_id: Url,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalSite018 {
+    pub registration_mode: RegistrationMode,
+    #[serde(default)]
+    pub enable_nsfw: bool,
+    #[serde(default)]
+    pub captcha_enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiteAggregates018 {
+    pub users: i64,
+    pub posts: i64,
+    pub comments: i64,
+    pub users_active_day: i64,
+    pub users_active_week: i64,
+    pub users_active_month: i64,
+    pub users_active_half_year: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersonView018 {
+    pub person: Person018,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Person018 {
+    pub actor_id: Url,
+    pub matrix_user_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetFederatedInstancesResponse018 {
+    pub federated_instances: Option<FederatedInstances018>,
+}
+
+/// Unlike 0.19, timestamps don't include a timezone and there is no federation state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederatedInstances018 {
+    pub linked: Vec<Instance018>,
+    pub allowed: Vec<Instance018>,
+    pub blocked: Vec<Instance018>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Instance018 {
+    pub domain: String,
+    pub software: Option<String>,
+    pub version: Option<String>,
+}
+
+// PieFed implements a Lemmy compatible API under /api/alpha, but leaves out some fields
+
+/// Nested under `piefed` in the output, so that it isn't read back as a Lemmy 0.18 response with
+/// the same fields
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PieFedSite {
+    pub piefed: GetSiteResponsePieFed,
+}
+
+impl Deref for PieFedSite {
+    type Target = GetSiteResponsePieFed;
+
+    fn deref(&self) -> &Self::Target {
+        &self.piefed
+    }
+}
+
+impl DerefMut for PieFedSite {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.piefed
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetSiteResponsePieFed {
+    pub site_view: SiteViewPieFed,
+    #[serde(default)]
+    pub admins: Vec<PersonViewPieFed>,
+    pub version: String,
+    #[serde(default)]
+    pub all_languages: Vec<Language>,
+    #[serde(default)]
+    pub discussion_languages: Vec<LanguageId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SiteViewPieFed {
+    pub site: SitePieFed,
+    pub local_site: LocalSitePieFed,
+    #[serde(default)]
+    pub counts: SiteAggregatesPieFed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SitePieFed {
+    pub name: String,
+    pub sidebar: Option<String>,
+    pub description: Option<String>,
+    pub icon: Option<Url>,
+    pub actor_id: Url,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalSitePieFed {
+    pub registration_mode: RegistrationMode,
+    #[serde(default)]
+    pub enable_nsfw: bool,
+    #[serde(default)]
+    pub captcha_enabled: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SiteAggregatesPieFed {
+    pub users: i64,
+    pub posts: i64,
+    pub comments: i64,
+    pub users_active_day: i64,
+    pub users_active_week: i64,
+    pub users_active_month: i64,
+    pub users_active_half_year: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersonViewPieFed {
+    pub person: PersonPieFed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersonPieFed {
+    pub actor_id: Url,
+    pub matrix_user_id: Option<String>,
+}
+
+/// Outgoing federation queue of an instance to one linked instance, reported by Lemmy 0.19 and
+/// later
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FederationState {
+    /// Linked instance which activities are sent to
+    pub domain: String,
+    /// Publish time of the last activity which was sent successfully
+    pub last_successful_published_time: Option<DateTime<Utc>>,
+    /// Failed attempts since the last successful send
+    pub fail_count: i32,
+    pub next_retry: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct RawFederationState {
+    last_successful_published_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    fail_count: i32,
+    next_retry: Option<DateTime<Utc>>,
+}
+
+/// Read the federation state of each linked instance from a `federated_instances` response.
+/// Parsed from JSON, as the API types don't expose all fields.
+pub fn parse_federation_states(response: &Value) -> Vec<FederationState> {
+    let linked = response["federated_instances"]["linked"].as_array();
+    linked
+        .into_iter()
+        .flatten()
+        .filter_map(|i| {
+            let domain = i["domain"]
+                .as_str()
+                .or_else(|| i["instance"]["domain"].as_str())?;
+            let state = RawFederationState::deserialize(i.get("federation_state")?).ok()?;
+            Some(FederationState {
+                domain: domain.to_string(),
+                last_successful_published_time: state.last_successful_published_time,
+                fail_count: state.fail_count,
+                next_retry: state.next_retry,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Lemmy 0.18 and PieFed responses have the same required fields
+    fn site_json(version: &str) -> Value {
+        json!({
+            "site_view": {
+                "site": {"name": "Example", "actor_id": "https://example.com/"},
+                "local_site": {"registration_mode": "Open"},
+                "counts": {
+                    "users": 10,
+                    "posts": 20,
+                    "comments": 30,
+                    "users_active_day": 1,
+                    "users_active_week": 2,
+                    "users_active_month": 3,
+                    "users_active_half_year": 5
+                }
+            },
+            "admins": [],
+            "version": version,
+            "all_languages": [],
+            "discussion_languages": []
+        })
+    }
+
+    fn round_trip(site_info: &GetSiteResponse) -> GetSiteResponse {
+        let json = serde_json::to_value(site_info).unwrap();
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn node_info_usage_reads_nodeinfo_and_output() {
+        let usage: NodeInfoUsage =
+            serde_json::from_value(json!({"localPosts": 20, "localComments": 30})).unwrap();
+        assert_eq!(usage.posts, 20);
+        assert_eq!(usage.comments, 30);
+        // Written as `posts` and `comments` in the output
+        let usage: NodeInfoUsage =
+            serde_json::from_value(serde_json::to_value(&usage).unwrap()).unwrap();
+        assert_eq!(usage.posts, 20);
+        assert_eq!(usage.comments, 30);
+    }
+
+    #[test]
+    fn piefed_round_trips() {
+        let site_info = GetSiteResponse::PieFed(PieFedSite {
+            piefed: serde_json::from_value(site_json("1.0.0")).unwrap(),
+        });
+        let site_info = round_trip(&site_info);
+        assert!(matches!(site_info, GetSiteResponse::PieFed(_)));
+        assert_eq!(site_info.api_version(), "piefed");
+        assert_eq!(site_info.version(), "1.0.0");
+    }
+
+    #[test]
+    fn lemmy_018_round_trips() {
+        let site_info = GetSiteResponse::V018(serde_json::from_value(site_json("0.18.5")).unwrap());
+        let site_info = round_trip(&site_info);
+        assert!(matches!(site_info, GetSiteResponse::V018(_)));
+        assert_eq!(site_info.version(), "0.18.5");
+        assert_eq!(site_info.users_active_month(), 3);
+    }
 }
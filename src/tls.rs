@@ -0,0 +1,146 @@
+use crate::connect::Connector;
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, TimeZone, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::{ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::{
+    Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName,
+};
+use tokio_rustls::TlsConnector;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Certificate of an instance, only with `--collect-tls`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsInfo {
+    /// Distinguished name of the issuer of the instance certificate
+    pub issuer: String,
+    pub not_after: DateTime<Utc>,
+    /// The certificate chain is valid for the domain, with the Mozilla root certificates
+    pub chain_valid: bool,
+    /// Why the chain is not valid
+    pub validation_error: Option<String>,
+}
+
+/// Validates certificates like browsers do
+static VERIFYING_CONFIG: Lazy<Arc<ClientConfig>> = Lazy::new(|| {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+});
+
+/// Accepts any certificate, so that details of invalid certificates can be read as well
+static ACCEPTING_CONFIG: Lazy<Arc<ClientConfig>> = Lazy::new(|| {
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate))
+        .with_no_client_auth();
+    Arc::new(config)
+});
+
+struct AcceptAnyCertificate;
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Connect to port 443 of the domain and read its certificate, with the resolver and rate limit
+/// of the connector
+pub(crate) async fn fetch_tls_info(domain: &str, connector: &Connector) -> Result<TlsInfo, Error> {
+    let addrs = connector.lookup(domain).await?;
+    let (certificate, validation_error) =
+        match peer_certificate(domain, &addrs, connector, &VERIFYING_CONFIG).await {
+            Ok(certificate) => (certificate, None),
+            Err(e) if is_certificate_error(&e) => {
+                let certificate =
+                    peer_certificate(domain, &addrs, connector, &ACCEPTING_CONFIG).await?;
+                (certificate, Some(e.to_string()))
+            }
+            Err(e) => return Err(e),
+        };
+    let (_, certificate) = X509Certificate::from_der(&certificate.0)
+        .map_err(|e| anyhow!("invalid certificate: {e}"))?;
+    let not_after = Utc
+        .timestamp_opt(certificate.validity().not_after.timestamp(), 0)
+        .single()
+        .ok_or_else(|| anyhow!("certificate expiry out of range"))?;
+    Ok(TlsInfo {
+        issuer: certificate.issuer().to_string(),
+        not_after,
+        chain_valid: validation_error.is_none(),
+        validation_error,
+    })
+}
+
+/// tokio-rustls wraps handshake errors in io errors
+fn is_certificate_error(error: &Error) -> bool {
+    let rustls_error = error
+        .downcast_ref::<io::Error>()
+        .and_then(|e| e.get_ref())
+        .and_then(|e| e.downcast_ref::<tokio_rustls::rustls::Error>());
+    matches!(
+        rustls_error,
+        Some(tokio_rustls::rustls::Error::InvalidCertificate(_))
+    )
+}
+
+/// Complete a TLS handshake on the connection, accepting any certificate, eg to time it
+pub(crate) async fn handshake(domain: &str, stream: TcpStream) -> Result<(), Error> {
+    tls_connect(domain, stream, &ACCEPTING_CONFIG).await?;
+    Ok(())
+}
+
+async fn tls_connect(
+    domain: &str,
+    stream: TcpStream,
+    config: &Arc<ClientConfig>,
+) -> Result<TlsStream<TcpStream>, Error> {
+    let server_name = ServerName::try_from(domain)?;
+    Ok(TlsConnector::from(config.clone())
+        .connect(server_name, stream)
+        .await?)
+}
+
+async fn peer_certificate(
+    domain: &str,
+    addrs: &[SocketAddr],
+    connector: &Connector,
+    config: &Arc<ClientConfig>,
+) -> Result<Certificate, Error> {
+    let stream = connector.connect(addrs).await?;
+    let stream = timeout(connector.timeout(), tls_connect(domain, stream, config)).await??;
+    let (_, connection) = stream.get_ref();
+    connection
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+        .cloned()
+        .ok_or_else(|| anyhow!("no certificate"))
+}
@@ -0,0 +1,141 @@
+use crate::crawl::CrawlResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Local communities, by top all time, whose names and titles are classified along with the
+/// site texts
+const TOP_COMMUNITIES: usize = 10;
+
+/// Keywords which have to appear in the texts of an instance for a topic
+const MIN_KEYWORD_MATCHES: usize = 2;
+
+/// Instances without a specific topic and with fewer local communities are niche
+const NICHE_MAX_COMMUNITIES: usize = 5;
+
+const TECH_KEYWORDS: &[&str] = &[
+    "tech",
+    "technology",
+    "programming",
+    "programmer",
+    "developer",
+    "developers",
+    "software",
+    "linux",
+    "foss",
+    "opensource",
+    "selfhosted",
+    "selfhosting",
+    "homelab",
+    "privacy",
+    "rust",
+    "python",
+    "hacker",
+    "hackers",
+];
+
+const GAMING_KEYWORDS: &[&str] = &[
+    "gaming",
+    "game",
+    "games",
+    "gamer",
+    "gamers",
+    "videogames",
+    "nintendo",
+    "playstation",
+    "xbox",
+    "steam",
+    "rpg",
+    "esports",
+];
+
+const REGIONAL_KEYWORDS: &[&str] = &[
+    "country",
+    "countries",
+    "region",
+    "regional",
+    "city",
+    "national",
+    "province",
+];
+
+const GENERAL_KEYWORDS: &[&str] = &[
+    "general",
+    "everyone",
+    "anything",
+    "everything",
+    "generalist",
+];
+
+/// Country code top level domains which are mostly used without regional meaning
+const GENERIC_COUNTRY_TLDS: &[&str] = &[
+    "ai", "cc", "co", "fm", "gg", "io", "me", "ml", "tk", "to", "tv", "ws",
+];
+
+/// Topical category of an instance, assigned by `classify` with keyword rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topic {
+    /// Open to all topics
+    General,
+    Tech,
+    /// For a country, region or city
+    Regional,
+    Gaming,
+    /// Focused on a topic which is not covered above
+    Niche,
+}
+
+/// Assign topics from keywords in the site name, description, sidebar and the names of the top
+/// local communities. Instances on a country code domain are regional. An instance without
+/// any of the specific topics is general, or niche if it has only a few local communities.
+pub fn classify(result: &CrawlResult) -> Vec<Topic> {
+    let site = &result.site_info;
+    let mut texts = vec![site.name()];
+    texts.extend(site.description());
+    texts.extend(site.sidebar());
+    for c in result.communities.iter().flatten().take(TOP_COMMUNITIES) {
+        texts.push(c.community.name.clone());
+        texts.push(c.community.title.clone());
+    }
+    let words: Vec<String> = texts
+        .iter()
+        .flat_map(|t| t.split(|c: char| !c.is_alphanumeric()))
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let matches = |keywords: &[&str]| {
+        let keywords: HashSet<&str> = keywords.iter().copied().collect();
+        words
+            .iter()
+            .filter(|w| keywords.contains(w.as_str()))
+            .count()
+            >= MIN_KEYWORD_MATCHES
+    };
+
+    let mut topics = vec![];
+    if matches(TECH_KEYWORDS) {
+        topics.push(Topic::Tech);
+    }
+    if matches(GAMING_KEYWORDS) {
+        topics.push(Topic::Gaming);
+    }
+    if matches(REGIONAL_KEYWORDS) || has_country_tld(&result.domain) {
+        topics.push(Topic::Regional);
+    }
+    if matches(GENERAL_KEYWORDS) {
+        topics.push(Topic::General);
+    }
+    if topics.is_empty() {
+        let communities = result.communities.as_ref().map(Vec::len);
+        match communities {
+            Some(c) if c < NICHE_MAX_COMMUNITIES => topics.push(Topic::Niche),
+            _ => topics.push(Topic::General),
+        }
+    }
+    topics
+}
+
+fn has_country_tld(domain: &str) -> bool {
+    let tld = domain.rsplit('.').next().unwrap_or_default();
+    tld.len() == 2 && !GENERIC_COUNTRY_TLDS.contains(&tld)
+}
@@ -0,0 +1,124 @@
+use crate::diff::CrawlDiff;
+use crate::notify::{Notification, Notifier};
+use crate::user_agent;
+use anyhow::Error;
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Url;
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use std::time::Duration;
+
+/// Header with the hex encoded HMAC-SHA256 of the request body, eg `sha256=0a1b...`. Only sent
+/// if a secret is configured.
+pub const SIGNATURE_HEADER: &str = "X-Lemmy-Stats-Signature";
+
+/// HTTP endpoint which receives the results of each crawl, eg to rebuild a website
+#[derive(Debug, Clone)]
+pub struct Webhook {
+    url: Url,
+    secret: Option<String>,
+    client: ClientWithMiddleware,
+}
+
+impl Webhook {
+    pub fn new(url: Url, secret: Option<String>) -> Result<Self, Error> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(60))
+            .user_agent(user_agent(None))
+            .build()?;
+        let retry_policy = ExponentialBackoff::builder().build_with_max_retries(5);
+        let client = ClientBuilder::new(client)
+            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+            .build();
+        Ok(Webhook {
+            url,
+            secret,
+            client,
+        })
+    }
+
+    /// POST the value as JSON, retrying on network errors and server errors
+    pub async fn send<T: Serialize>(&self, value: &T) -> Result<(), Error> {
+        let body = serde_json::to_vec(value)?;
+        let mut request = self
+            .client
+            .post(self.url.clone())
+            .header(CONTENT_TYPE, "application/json");
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, format!("sha256={}", sign(secret, &body)));
+        }
+        request.body(body).send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Data of a notification, with the changes since the previous crawl under `diff`
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    data: &'a Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<&'a CrawlDiff>,
+}
+
+/// Posts the data of each notification
+#[async_trait]
+impl Notifier for Webhook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<(), Error> {
+        self.send(&Payload {
+            data: &notification.data,
+            diff: notification.diff.as_ref(),
+        })
+        .await
+    }
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::TotalDeltas;
+    use serde_json::json;
+
+    #[test]
+    fn payload_adds_diff_next_to_data() {
+        let data = json!({"run_id": "b", "crawled_instances": 1});
+        let diff = CrawlDiff {
+            previous_run_id: "a".to_string(),
+            run_id: "b".to_string(),
+            new_instances: vec!["lemmy.ml".to_string()],
+            removed_instances: vec![],
+            total_deltas: TotalDeltas::default(),
+            version_changes: vec![],
+        };
+        let payload = serde_json::to_value(Payload {
+            data: &data,
+            diff: Some(&diff),
+        })
+        .unwrap();
+        assert_eq!(payload["run_id"], "b");
+        assert_eq!(payload["diff"]["new_instances"], json!(["lemmy.ml"]));
+
+        let payload = serde_json::to_value(Payload {
+            data: &data,
+            diff: None,
+        })
+        .unwrap();
+        assert_eq!(payload, data);
+    }
+}